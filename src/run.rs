@@ -1,4 +1,4 @@
-use crate::scene::BoidScene;
+use crate::scene::{BoidScene, BoidScene3d};
 use ready_paint::{
     gfx::{Gfx, LimitFPS},
     Render, RenderEntry,
@@ -134,7 +134,12 @@ impl ApplicationHandler for App {
                 return gfx;
             });
             self.render.entry = RenderEntry::Ready(gfx);
-            self.render.add_scene::<BoidScene>("check");
+            // `--3d` 切换到3D场景
+            if std::env::args().any(|arg| arg == "--3d") {
+                self.render.add_scene::<BoidScene3d>("check3d");
+            } else {
+                self.render.add_scene::<BoidScene>("check");
+            }
             self.first_resize = true;
         }
     }
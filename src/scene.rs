@@ -1,7 +1,9 @@
 use boid::Boid;
+use boid3d::Boid3d;
 use config::{BoidConfig, Config};
 use entity::{share::Share, Entity};
 use paint::Paint;
+use paint3d::Paint3d;
 use ready_paint::scene::Queue;
 use space::{draw::SpaceDraw, Space};
 use uniforms::Uniforms;
@@ -11,7 +13,10 @@ pub struct BoidScene;
 impl Queue for BoidScene {
     fn introduce(scene: &mut ready_paint::scene::Scene) {
         scene
-            .add_ready(Config::default())
+            .add_ready(Config {
+                wrap_space: true,
+                ..Config::default()
+            })
             .add_ready(BoidConfig::default())
             .add_ready(Entity::default())
             .add_ready(Uniforms::default())
@@ -22,9 +27,24 @@ impl Queue for BoidScene {
         scene.add_paint::<Paint>();
     }
 }
+
+/// 3D版本，用透视相机看整个群体
+pub struct BoidScene3d;
+
+impl Queue for BoidScene3d {
+    fn introduce(scene: &mut ready_paint::scene::Scene) {
+        scene
+            .add_ready(Config::default())
+            .add_ready(BoidConfig::default())
+            .add_ready::<Boid3d>(Boid3d::default());
+        scene.add_paint::<Paint3d>();
+    }
+}
 mod boid;
+mod boid3d;
 mod config;
 mod entity;
 mod paint;
+mod paint3d;
 mod space;
 mod uniforms;
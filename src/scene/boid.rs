@@ -1,4 +1,4 @@
-use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
 
 use glam::Vec2;
 use rand::Rng;
@@ -7,16 +7,35 @@ use ready_paint::{
     scene::{get_res, get_res_mut, return_res, Ready, Update},
 };
 
-use super::{config::BoidConfig, entity::Entity, space::Space};
+use collide::resolve_collisions;
+
+use super::{
+    config::{BoidConfig, Config, SpatialBackend},
+    entity::{handle::EntityHandle, instance::_CircleInstance, Entity},
+    space::{
+        dense::DenseGrid, morton_order, quadtree::LooseQuadtree, reorder_by, Clustering,
+        ClusteringMarker, Collision, CollisionMarker, Space, SpaceLevel, SpaceVector, SpatialIndex,
+    },
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
 #[derive(Default)]
 pub struct Boid {
     masses: Vec<f32>,
     accs: Vec<Vec2>,
-    velocities: Vec<[f32; 2]>,
+    velocities: Vec<Vec2>,
     target: Vec2,
+    // hash构建阶段的耗时统计，开启 `Config::debug_report` 时每 HASH_BUILD_REPORT_FRAMES 帧打印一次平均值
+    hash_build_time: Duration,
+    hash_build_frames: u32,
+    // 距离上一次按Z序重排过了多少帧
+    reorder_frames: u32,
 }
 
+const HASH_BUILD_REPORT_FRAMES: u32 = 300;
+
 impl Ready for Boid {
     fn ready(&mut self, data: &mut ready_paint::scene::HashTypeId2Data, _: &ready_paint::gfx::Gfx) {
         let entity = get_res_mut::<Entity>(data);
@@ -26,10 +45,9 @@ impl Ready for Boid {
         let radius = instance_collect.clone();
         let masses: Vec<f32> = radius.iter().map(|i| i.radius).collect();
         let accs: Vec<Vec2> = (0..counts).map(|_| base_acc).collect();
-        let velocities: Vec<[f32; 2]> = instance_collect
-            .clone()
+        let velocities: Vec<Vec2> = instance_collect
             .iter()
-            .map(|i| i.velocity)
+            .map(|i| Vec2::from_array(i.velocity))
             .collect();
         return_res(
             data,
@@ -38,6 +56,7 @@ impl Ready for Boid {
                 accs,
                 velocities,
                 target: Vec2::new(400., 400.),
+                ..Default::default()
             },
         );
     }
@@ -45,10 +64,60 @@ impl Ready for Boid {
 
 impl Update for Boid {
     fn update(data: &mut ready_paint::scene::HashTypeId2Data, gfx: &ready_paint::gfx::Gfx) {
+        let config = get_res::<Config>(data);
+        let clustering_backend = config.clustering_backend;
+        match config.collision_backend {
+            SpatialBackend::Hash => Boid::step_with::<Collision>(clustering_backend, data, gfx),
+            SpatialBackend::Dense => {
+                Boid::step_with::<DenseGrid<CollisionMarker>>(clustering_backend, data, gfx)
+            }
+            SpatialBackend::Quadtree => {
+                Boid::step_with::<LooseQuadtree<CollisionMarker>>(clustering_backend, data, gfx)
+            }
+        }
+    }
+}
+
+impl Boid {
+    fn step_with<C: SpaceLevel>(
+        clustering_backend: SpatialBackend,
+        data: &mut ready_paint::scene::HashTypeId2Data,
+        gfx: &ready_paint::gfx::Gfx,
+    ) {
+        match clustering_backend {
+            SpatialBackend::Hash => Boid::step::<C, Clustering>(data, gfx),
+            SpatialBackend::Dense => Boid::step::<C, DenseGrid<ClusteringMarker>>(data, gfx),
+            SpatialBackend::Quadtree => {
+                Boid::step::<C, LooseQuadtree<ClusteringMarker>>(data, gfx)
+            }
+        }
+    }
+
+    /// 一帧的boid更新，碰撞层和聚类层分别使用 `C` 和 `K` 两种空间索引
+    /// 对应类型的层需要已经注册在 `Space` 中
+    pub fn step<C: SpaceLevel, K: SpaceLevel>(
+        data: &mut ready_paint::scene::HashTypeId2Data,
+        gfx: &ready_paint::gfx::Gfx,
+    ) {
         let dt = gfx.delta_time;
+        let debug_report = get_res::<Config>(data).debug_report;
+        let reorder_every = get_res::<Config>(data).morton_reorder_frames;
         let config = gfx.surface_config.as_ref().unwrap();
         let (entity, boid, space, boid_config) =
             refs_muts::<(Mut<Entity>, Mut<Boid>, Mut<Space>, Ref<BoidConfig>)>(data);
+        // 按碰撞层的网格做Z序，没有固定cell的索引用碰撞查询半径
+        if let Some(every) = reorder_every {
+            boid.reorder_frames += 1;
+            if boid.reorder_frames >= every {
+                boid.reorder_frames = 0;
+                let collision_space = space.index::<C>();
+                let cell_size = collision_space
+                    .cell_size()
+                    .unwrap_or(Vec2::splat(boid_config.collision_radius()));
+                let origin = collision_space.cell_origin();
+                boid.reorder(entity, space, cell_size, origin);
+            }
+        }
         let entity_poses = entity
             .instance_collect
             .as_mut()
@@ -57,153 +126,69 @@ impl Update for Boid {
             .map(|i| Vec2::from_array(i.position))
             .collect::<Vec<Vec2>>();
 
-        let collision_space = &mut space.maps.0;
-        let clustering_space = &mut space.maps.1;
-
-        // 更新空间
-        collision_space.clear();
-        clustering_space.clear();
-
-        for (i, pos) in entity_poses.iter().enumerate() {
-            collision_space.insert(i as u32, *pos);
-            clustering_space.insert(i as u32, *pos);
+        // 更新空间，只有跨cell的实体才会改动map
+        // entity.entity_poses 是上一次写入空间时的位置
+        // 聚类层的cell里还存着速度，位置更新之后一起刷新
+        let hash_build_start = Instant::now();
+        let last_poses = entity.entity_poses.as_mut().unwrap();
+        space.update_positions(last_poses, &entity_poses);
+        space.update_velocities(&boid.velocities);
+        last_poses.copy_from_slice(&entity_poses);
+        if debug_report {
+            boid.record_hash_build(hash_build_start.elapsed(), space);
         }
-
+        // cell大小由 Space 自己调整，要不要打印由这里决定
+        let retunes = space.auto_tune();
+        if debug_report {
+            for retune in retunes {
+                println!("{}", retune);
+            }
+        }
+        let collision_space = space.index::<C>();
+        let clustering_space = space.index::<K>();
 
         // 目标位置的更新逻辑
 
-        // 实体insert到哈希空间
-
-        let velocities = boid.velocities.deref_mut();
-        let accs = boid.accs.deref_mut();
-        let masses = boid.masses.deref_mut();
+        // 每个boid的新速度只依赖上一帧的位置快照
+        let new_velocities = steer_all(
+            &entity_poses,
+            &boid.velocities,
+            boid.target,
+            clustering_space,
+            boid_config,
+            dt,
+        );
 
-        for (i, (pos, (acc, (mass, velocity)))) in entity_poses
+        let instances = entity.instance_collect.as_mut().unwrap();
+        let mut velocities = new_velocities;
+        let mut positions: Vec<Vec2> = entity_poses
             .iter()
-            .zip(accs.iter().zip(masses.iter().zip(velocities.iter())))
-            .enumerate()
+            .zip(velocities.iter())
+            .map(|(position, velocity)| *position + *velocity * dt)
+            .collect();
+        // 碰撞: 候选对从碰撞层里取，范围留出这一帧两边各自可能移动的距离
+        if boid_config.collision_iterations > 0 {
+            let radii: Vec<f32> = instances.iter().map(|i| i.radius).collect();
+            let max_radius = radii.iter().fold(0f32, |max, r| max.max(*r));
+            let reach = 2. * (max_radius + boid_config.max_speed * dt);
+            let candidates = collision_space.pairs_within(&entity_poses, reach);
+            resolve_collisions(
+                collision_space,
+                &candidates,
+                &mut positions,
+                &mut velocities,
+                &radii,
+                &boid.masses,
+                boid_config.collision_restitution,
+                boid_config.collision_iterations,
+            );
+        }
+        for ((instance, position), velocity) in
+            instances.iter_mut().zip(positions).zip(velocities.iter())
         {
-            let current_pos = pos;
-            let mut separation = Vec2::ZERO;
-            let mut alignment = Vec2::ZERO;
-            let mut cohesion = Vec2::ZERO;
-            let mut neighbors = 0;
-
-            // 分离: 避免碰撞
-            let index_grid = collision_space.query(*current_pos).unwrap();
-
-
-            // 对齐和内聚: 使用更大的范围
-            let index_grid = clustering_space.query(*current_pos).unwrap();
-            for neighbor_id in index_grid.entity_ids.iter() {
-                if *neighbor_id as usize == i {
-                    continue;
-                }
-                let neighbor_pos = entity_poses[*neighbor_id as usize];
-                let diff = current_pos - neighbor_pos;
-                let dist = diff.length();
-
-                // 对齐: 速度方向一致
-                if dist < boid_config.alignment_max_radius
-                    && dist > boid_config.alignment_min_radius
-                {
-                    let neighbor_vel = Vec2::from_slice(&velocities[*neighbor_id as usize]);
-                    alignment += neighbor_vel;
-                }
-
-                // 内聚: 向群体中心移动
-                if dist < boid_config.cohesion_radius && dist > 0.0 {
-                    cohesion += neighbor_pos;
-                    neighbors += 1;
-                }
-            }
-
-            if neighbors > 0 {
-                alignment /= neighbors as f32;
-                cohesion = cohesion / neighbors as f32 - current_pos;
-            }
-
-            // 计算期望的速度方向， 三力合一，加一个目标力
-            let desired_direction = {
-                let mut dir = Vec2::ZERO;
-
-                // 添加各种力的影响
-                if separation.length() > 0.0 {
-                    dir += separation.normalize() * boid_config.separation_weight;
-                    // 分离力
-                }
-                if alignment.length() > 0.0 {
-                    dir += alignment.normalize() * boid_config.alignment_weight;
-                    // 对齐力
-                }
-                if cohesion.length() > 0.0 {
-                    dir += cohesion.normalize() * boid_config.cohesion_weight; // 内聚力
-                }
-
-                // 目标力
-                let to_target = boid.target - *current_pos;
-                if to_target.length() > 0.0 {
-                    let mut target_influence =
-                        (to_target.length() / boid_config.target_influence_scale).min(1.2);
-                    if to_target.length() < boid_config.target_min_distance {
-                        target_influence = 0.05;
-                    }
-                    dir += to_target.normalize() * target_influence * boid_config.target_weight;
-                    // 增加目标影响
-                }
-
-                if dir.length() > 0.0 {
-                    dir.normalize()
-                } else {
-                    Vec2::from_slice(velocity).normalize()
-                }
-            };
-
-            // 获取当前速度方向
-            let current_direction = Vec2::from_slice(velocity).normalize();
-
-            // 计算转向力
-            let steer = {
-                let dot = current_direction.dot(desired_direction);
-                let angle = dot.clamp(-1.0, 1.0).acos(); // 防止数值误差
-
-                // 使用 slerp 进行平滑转向
-                let angle_factor = (angle / std::f32::consts::PI).min(1.0);
-                let t = boid_config.steer_strength
-                    * (1.0 - angle_factor * boid_config.steer_angle_factor);
-                let steer_dir = if angle < 0.001 {
-                    desired_direction
-                } else {
-                    let sin_angle = angle.sin();
-                    if sin_angle < 0.001 {
-                        desired_direction
-                    } else {
-                        let s0 = (angle * (1.0 - t)).sin() / sin_angle;
-                        let s1 = (angle * t).sin() / sin_angle;
-                        (current_direction * s0 + desired_direction * s1).normalize()
-                    }
-                };
-
-                steer_dir * boid_config.max_steer_force
-            };
-
-            // 应用转向力来更新速度
-            let mut new_velocity =
-                Vec2::from_slice(velocity) + steer * dt * boid_config.base_acc_scale;
-
-            // 速度限制
-            let speed = new_velocity.length();
-            if speed < boid_config.min_speed {
-                new_velocity = new_velocity.normalize() * boid_config.min_speed;
-            } else if speed > boid_config.max_speed {
-                new_velocity = new_velocity.normalize() * boid_config.max_speed;
-            }
-
             // 更新实例数据
-            let instance = &mut entity.instance_collect.as_mut().unwrap()[i];
-            instance.velocity = new_velocity.to_array();
-            instance.position[0] += new_velocity.x * dt;
-            instance.position[1] += new_velocity.y * dt;
+            instance.velocity = velocity.to_array();
+            instance.position = position.to_array();
             // 边界处理
             let margin = boid_config.boundary_margin;
             let width = config.width as f32;
@@ -222,9 +207,20 @@ impl Update for Boid {
                 instance.position[1] = -margin;
             }
         }
-        let instance_buffer = entity.instance_buffer.as_mut().unwrap();
+        // 碰撞冲量要留到下一帧的转向里
+        boid.velocities = velocities;
         let data_bytes = bytemuck::cast_slice(entity.instance_collect.as_ref().unwrap().as_slice());
-        gfx.queue.write_buffer(instance_buffer, 0, data_bytes);
+        // spawn之后实体可能比buffer多，重新建一个刚好装下的
+        if entity.instance_buffer.as_ref().unwrap().size() < data_bytes.len() as u64 {
+            entity.instance_buffer = Some(gfx.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("single_buffer"),
+                contents: data_bytes,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            }));
+        } else {
+            let instance_buffer = entity.instance_buffer.as_mut().unwrap();
+            gfx.queue.write_buffer(instance_buffer, 0, data_bytes);
+        }
     }
 }
 
@@ -232,5 +228,520 @@ impl Boid {
     pub fn set_target(&mut self, target: Vec2) {
         self.target = target;
     }
+
+    /// 加一个boid并写入空间的所有层，返回的句柄可以跨帧使用
+    pub fn spawn(
+        &mut self,
+        entity: &mut Entity,
+        space: &mut Space,
+        instance: _CircleInstance,
+    ) -> EntityHandle {
+        let index = self.velocities.len() as u32;
+        space.insert(index, Vec2::from_array(instance.position));
+        self.masses.push(instance.radius);
+        self.accs.push(Vec2::ZERO);
+        self.velocities.push(Vec2::from_array(instance.velocity));
+        entity.spawn(instance)
+    }
+
+    /// 删除一个boid，最后一个boid挪到空出来的下标，空间里它的id跟着改
+    /// 句柄已经失效时什么都不做，返回 `false`
+    pub fn despawn(
+        &mut self,
+        entity: &mut Entity,
+        space: &mut Space,
+        handle: EntityHandle,
+    ) -> bool {
+        let Some(index) = entity.index_of(handle) else {
+            return false;
+        };
+        let poses = entity.entity_poses.as_ref().unwrap();
+        let last = poses.len() - 1;
+        space.remove(index as u32, poses[index]);
+        if index != last {
+            space.remove(last as u32, poses[last]);
+            space.insert(index as u32, poses[last]);
+        }
+        entity.despawn(handle);
+        self.masses.swap_remove(index);
+        self.accs.swap_remove(index);
+        self.velocities.swap_remove(index);
+        true
+    }
+
+    /// 按所在cell的Z序重排boid，空间上相邻的boid在内存里也相邻，邻居循环的缓存命中更好
+    /// 实例、速度、质量一起重排，空间里的id原地改写，句柄仍然指向原来的boid
+    pub fn reorder(
+        &mut self,
+        entity: &mut Entity,
+        space: &mut Space,
+        cell_size: Vec2,
+        origin: Vec2,
+    ) {
+        let last_poses = entity.entity_poses.as_ref().unwrap();
+        let order = morton_order(last_poses, cell_size, origin);
+        entity.permute(&order);
+        reorder_by(&mut self.masses, &order);
+        reorder_by(&mut self.accs, &order);
+        reorder_by(&mut self.velocities, &order);
+        space.remap_ids(&order, entity.entity_poses.as_ref().unwrap());
+    }
+
+    /// 句柄对应boid当前的速度，失效的句柄返回 `None`
+    pub fn velocity(&self, entity: &Entity, handle: EntityHandle) -> Option<Vec2> {
+        self.velocities.get(entity.index_of(handle)?).copied()
+    }
+
+    /// 同时打印每一层的占用统计，cell大小不合适时能直接看出来
+    fn record_hash_build(&mut self, elapsed: Duration, space: &Space) {
+        self.hash_build_time += elapsed;
+        self.hash_build_frames += 1;
+        if self.hash_build_frames == HASH_BUILD_REPORT_FRAMES {
+            println!(
+                "hash build avg: {:?} ({} entities)",
+                self.hash_build_time / self.hash_build_frames,
+                self.velocities.len()
+            );
+            for (name, stats) in space.stats() {
+                println!("  {}: {}", name, stats);
+            }
+            self.hash_build_time = Duration::ZERO;
+            self.hash_build_frames = 0;
+        }
+    }
 }
+/// 所有boid这一帧的新速度，开启 rayon feature 时并行计算，结果和串行完全一样
+pub(super) fn steer_all<V, K>(
+    entity_poses: &[V],
+    velocities: &[V],
+    target: V,
+    clustering_space: &K,
+    boid_config: &BoidConfig,
+    dt: f32,
+) -> Vec<V>
+where
+    V: SpaceVector,
+    K: SpatialIndex<V> + Sync + ?Sized,
+{
+    let steer = |i: usize| {
+        steer_velocity(
+            i,
+            entity_poses,
+            velocities,
+            target,
+            clustering_space,
+            boid_config,
+            dt,
+        )
+    };
+    #[cfg(feature = "rayon")]
+    let steered = (0..entity_poses.len()).into_par_iter().map(steer).collect();
+    #[cfg(not(feature = "rayon"))]
+    let steered = (0..entity_poses.len()).map(steer).collect();
+    steered
+}
+
+/// 第 `i` 个boid这一帧的新速度，只读取位置/速度快照和空间索引
+/// 对向量维度泛型，2D和3D场景共用同一套规则
+fn steer_velocity<V, K>(
+    i: usize,
+    entity_poses: &[V],
+    velocities: &[V],
+    target: V,
+    clustering_space: &K,
+    boid_config: &BoidConfig,
+    dt: f32,
+) -> V
+where
+    V: SpaceVector,
+    K: SpatialIndex<V> + ?Sized,
+{
+    let current_pos = &entity_poses[i];
+    let velocity = velocities[i];
+    let separation = V::ZERO;
+    let mut alignment = V::ZERO;
+    let mut cohesion = V::ZERO;
+    let mut neighbors = 0;
+
+    // 分离: 避免碰撞，目前碰撞层还没有参与转向，分离力为零
+
+    // 对齐和内聚: 使用更大的范围，跨cell查询避免在网格线上聚团
+    // 聚类层是 `Clustering` 时offset和速度都直接从cell里读，不用回到实体数组
+    clustering_space.for_each_neighbor(
+        *current_pos,
+        boid_config.clustering_radius(),
+        entity_poses,
+        velocities,
+        &mut |neighbor_id, offset, neighbor_vel| {
+            if neighbor_id as usize == i {
+                return;
+            }
+            let neighbor_pos = *current_pos + offset;
+            let dist = offset.length();
+
+            // 对齐: 速度方向一致
+            if dist < boid_config.alignment_max_radius && dist > boid_config.alignment_min_radius {
+                alignment += neighbor_vel;
+            }
+
+            // 内聚: 向群体中心移动
+            if dist < boid_config.cohesion_radius && dist > 0.0 {
+                cohesion += neighbor_pos;
+                neighbors += 1;
+            }
+        },
+    );
+
+    if neighbors > 0 {
+        alignment /= neighbors as f32;
+        cohesion = cohesion / neighbors as f32 - *current_pos;
+    }
+
+    // 计算期望的速度方向， 三力合一，加一个目标力
+    let desired_direction = {
+        let mut dir = V::ZERO;
+
+        // 添加各种力的影响
+        if separation.length() > 0.0 {
+            dir += separation.normalize() * boid_config.separation_weight;
+            // 分离力
+        }
+        if alignment.length() > 0.0 {
+            dir += alignment.normalize() * boid_config.alignment_weight;
+            // 对齐力
+        }
+        if cohesion.length() > 0.0 {
+            dir += cohesion.normalize() * boid_config.cohesion_weight; // 内聚力
+        }
+
+        // 目标力
+        let to_target = target - *current_pos;
+        if to_target.length() > 0.0 {
+            let mut target_influence =
+                (to_target.length() / boid_config.target_influence_scale).min(1.2);
+            if to_target.length() < boid_config.target_min_distance {
+                target_influence = 0.05;
+            }
+            dir += to_target.normalize() * target_influence * boid_config.target_weight;
+            // 增加目标影响
+        }
+
+        if dir.length() > 0.0 {
+            dir.normalize()
+        } else {
+            velocity.normalize()
+        }
+    };
+
+    // 获取当前速度方向
+    let current_direction = velocity.normalize();
+
+    // 计算转向力
+    let steer = {
+        let dot = current_direction.dot(desired_direction);
+        let angle = dot.clamp(-1.0, 1.0).acos(); // 防止数值误差
+
+        // 使用 slerp 进行平滑转向
+        let angle_factor = (angle / std::f32::consts::PI).min(1.0);
+        let t = boid_config.steer_strength * (1.0 - angle_factor * boid_config.steer_angle_factor);
+        let steer_dir = if angle < 0.001 {
+            desired_direction
+        } else {
+            let sin_angle = angle.sin();
+            if sin_angle < 0.001 {
+                desired_direction
+            } else {
+                let s0 = (angle * (1.0 - t)).sin() / sin_angle;
+                let s1 = (angle * t).sin() / sin_angle;
+                (current_direction * s0 + desired_direction * s1).normalize()
+            }
+        };
+
+        steer_dir * boid_config.max_steer_force
+    };
+
+    // 应用转向力来更新速度
+    let mut new_velocity = velocity + steer * dt * boid_config.base_acc_scale;
+
+    // 速度限制
+    let speed = new_velocity.length();
+    if speed < boid_config.min_speed {
+        new_velocity = new_velocity.normalize() * boid_config.min_speed;
+    } else if speed > boid_config.max_speed {
+        new_velocity = new_velocity.normalize() * boid_config.max_speed;
+    }
+    new_velocity
+}
+
+#[test]
+fn clustering_payload_matches_indexed_lookup() {
+    use super::space::SpaceMap;
+    use rand::SeedableRng;
+    let boid_config = BoidConfig::default();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(22);
+    let mut positions: Vec<Vec2> = (0..800)
+        .map(|_| Vec2::new(rng.gen_range(0.0..400.0), rng.gen_range(0.0..300.0)))
+        .collect();
+    let mut velocities: Vec<Vec2> = (0..800)
+        .map(|_| Vec2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)))
+        .collect();
+    // 同样的网格，一个cell里存速度，一个只存id
+    let mut clustering = Clustering::with_hasher(Vec2::new(60., 60.), Default::default());
+    let mut by_id =
+        SpaceMap::<ClusteringMarker>::with_hasher(Vec2::new(60., 60.), Default::default());
+    SpatialIndex::build(&mut clustering, &positions);
+    SpatialIndex::build(&mut by_id, &positions);
+    for _ in 0..3 {
+        clustering.update_velocities(&velocities);
+        let steer = |clustering: &dyn SpatialIndex| {
+            (0..positions.len())
+                .map(|i| {
+                    steer_velocity(
+                        i,
+                        &positions,
+                        &velocities,
+                        Vec2::new(200., 150.),
+                        clustering,
+                        &boid_config,
+                        1. / 60.,
+                    )
+                })
+                .collect::<Vec<Vec2>>()
+        };
+        let stored = steer(&clustering);
+        assert_eq!(stored, steer(&by_id));
+
+        let old = positions.clone();
+        for (p, v) in positions.iter_mut().zip(stored.iter()) {
+            *p += *v * 0.5;
+        }
+        velocities = stored;
+        clustering.update_positions(&old, &positions);
+        by_id.update_positions(&old, &positions);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_steer_matches_serial() {
+    use rand::SeedableRng;
+    let boid_config = BoidConfig::default();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(14);
+    let positions: Vec<Vec2> = (0..2000)
+        .map(|_| Vec2::new(rng.gen_range(0.0..800.0), rng.gen_range(0.0..600.0)))
+        .collect();
+    let velocities: Vec<Vec2> = (0..2000)
+        .map(|_| Vec2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)))
+        .collect();
+    let mut clustering = Clustering::with_hasher(Vec2::new(60., 60.), Default::default());
+    SpatialIndex::build(&mut clustering, &positions);
+    clustering.update_velocities(&velocities);
+    let target = Vec2::new(400., 300.);
+    let serial: Vec<Vec2> = (0..positions.len())
+        .map(|i| {
+            steer_velocity(
+                i,
+                &positions,
+                &velocities,
+                target,
+                &clustering,
+                &boid_config,
+                1. / 60.,
+            )
+        })
+        .collect();
+    let parallel = steer_all(
+        &positions,
+        &velocities,
+        target,
+        &clustering,
+        &boid_config,
+        1. / 60.,
+    );
+    assert_eq!(serial, parallel);
+}
+mod collide;
 mod entry;
+
+#[test]
+fn despawn_keeps_handles_and_space_in_sync() {
+    let collision = Collision::with_hasher(Vec2::new(10., 10.), Default::default());
+    let mut space = Space::default().with_level("collision", collision);
+    let mut entity = Entity::default();
+    let mut boid = Boid::default();
+    let instance = |x: f32, vx: f32| _CircleInstance {
+        position: [x, 0.],
+        velocity: [vx, 0.],
+        radius: 2.,
+    };
+    let a = boid.spawn(&mut entity, &mut space, instance(5., 1.));
+    let b = boid.spawn(&mut entity, &mut space, instance(25., 2.));
+    let c = boid.spawn(&mut entity, &mut space, instance(45., 3.));
+
+    // 删掉a之后c挪到下标0，空间里的id也跟着换
+    assert!(boid.despawn(&mut entity, &mut space, a));
+    assert!(!boid.despawn(&mut entity, &mut space, a));
+    assert_eq!(boid.velocity(&entity, a), None);
+    assert_eq!(boid.velocity(&entity, c), Some(Vec2::new(3., 0.)));
+    assert_eq!(entity.index_of(c), Some(0));
+    assert_eq!(entity.instance(b).unwrap().position, [25., 0.]);
+    let collision = space.level::<CollisionMarker>();
+    assert_eq!(collision.query_radius(Vec2::new(45., 0.), 1.), vec![0]);
+    assert_eq!(collision.query_radius(Vec2::new(25., 0.), 1.), vec![1]);
+    assert!(collision.query_radius(Vec2::new(5., 0.), 1.).is_empty());
+
+    // 新boid复用a的槽位，但a仍然失效
+    let d = boid.spawn(&mut entity, &mut space, instance(65., 4.));
+    assert_eq!(d.index(), a.index());
+    assert!(!entity.is_alive(a));
+    assert_eq!(boid.velocity(&entity, d), Some(Vec2::new(4., 0.)));
+    assert_eq!(entity.handle_at(2), d);
+}
+
+#[test]
+fn queries_after_slot_reuse_return_the_new_handle() {
+    use super::entity::handle::UnknownEntityId;
+    let collision = Collision::with_hasher(Vec2::new(10., 10.), Default::default());
+    let mut space = Space::default().with_level("collision", collision);
+    let mut entity = Entity::default();
+    let mut boid = Boid::default();
+    let instance = |x: f32| _CircleInstance {
+        position: [x, 0.],
+        velocity: [1., 0.],
+        radius: 2.,
+    };
+    let a = boid.spawn(&mut entity, &mut space, instance(5.));
+    let b = boid.spawn(&mut entity, &mut space, instance(25.));
+    let collision = space.level::<CollisionMarker>();
+    assert_eq!(
+        entity.query_radius(collision, Vec2::new(5., 0.), 1.),
+        Ok(vec![a])
+    );
+
+    // 新boid复用a的槽位并且放在a原来的位置，查询只能给出新句柄
+    assert!(boid.despawn(&mut entity, &mut space, a));
+    let c = boid.spawn(&mut entity, &mut space, instance(5.));
+    assert_eq!(c.index(), a.index());
+    assert_ne!(c, a);
+    let collision = space.level::<CollisionMarker>();
+    let near = entity
+        .query_radius(collision, Vec2::new(5., 0.), 1.)
+        .unwrap();
+    assert_eq!(near, vec![c]);
+    assert!(!near.contains(&a));
+    assert_eq!(entity.nearest(collision, Vec2::new(4., 0.)), Ok(Some(c)));
+    let mut in_box = entity
+        .query_aabb(collision, Vec2::new(0., -1.), Vec2::new(30., 1.))
+        .unwrap();
+    in_box.sort_by_key(|h| h.index());
+    assert_eq!(in_box, vec![c, b]);
+    assert_eq!(entity.pairs_within(collision, 25.), Ok(vec![(b, c, 20.)]));
+    assert_eq!(
+        entity.handles(collision.nearest_k(Vec2::ZERO, 2, 100.)),
+        Ok(vec![c, b])
+    );
+    // 空间里有实体数组之外的id时报错，不会悄悄丢掉
+    assert_eq!(entity.handles([0, 7]), Err(UnknownEntityId(7)));
+    assert!(!entity.is_alive(a));
+}
+
+#[test]
+fn reorder_keeps_handles_on_their_boids() {
+    let collision = Collision::with_hasher(Vec2::new(10., 10.), Default::default());
+    let mut space = Space::default().with_level("collision", collision);
+    let mut entity = Entity::default();
+    let mut boid = Boid::default();
+    let handles: Vec<EntityHandle> = [95., 5., 55., 15.]
+        .iter()
+        .map(|x| {
+            let instance = _CircleInstance {
+                position: [*x, 0.],
+                velocity: [*x, 1.],
+                radius: 2.,
+            };
+            boid.spawn(&mut entity, &mut space, instance)
+        })
+        .collect();
+
+    boid.reorder(&mut entity, &mut space, Vec2::new(10., 10.), Vec2::ZERO);
+    let indices: Vec<usize> = handles
+        .iter()
+        .map(|h| entity.index_of(*h).unwrap())
+        .collect();
+    assert_eq!(indices, vec![3, 0, 2, 1]);
+    for (handle, x) in handles.iter().zip([95., 5., 55., 15.]) {
+        assert_eq!(boid.velocity(&entity, *handle), Some(Vec2::new(x, 1.)));
+        assert_eq!(entity.instance(*handle).unwrap().position, [x, 0.]);
+        let index = entity.index_of(*handle).unwrap() as u32;
+        let collision = space.level::<CollisionMarker>();
+        assert_eq!(collision.query_radius(Vec2::new(x, 0.), 1.), vec![index]);
+    }
+}
+
+/// 打乱顺序和Z序两种存储下整套邻居循环的耗时
+/// `cargo test --release --features rayon -- --ignored --nocapture morton` 查看结果
+#[test]
+#[ignore]
+fn morton_reorder_speeds_up_neighbour_loop() {
+    use super::space::cell_size_for;
+    use rand::SeedableRng;
+    fn run(
+        positions: &[Vec2],
+        velocities: &[Vec2],
+        clustering: &Clustering,
+        boid_config: &BoidConfig,
+    ) -> (Duration, Vec<Vec2>) {
+        let mut best = Duration::MAX;
+        let mut result = Vec::new();
+        for _ in 0..5 {
+            let start = Instant::now();
+            let steered = steer_all(
+                positions,
+                velocities,
+                Vec2::ZERO,
+                clustering,
+                boid_config,
+                1. / 60.,
+            );
+            best = best.min(start.elapsed());
+            result = steered;
+        }
+        (best, result)
+    }
+    let boid_config = BoidConfig::default();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(25);
+    for n in [10_000, 50_000, 200_000] {
+        // 和默认场景差不多的密度，每1000平方像素一个boid
+        let side = (n as f32 * 1000.).sqrt();
+        let mut positions: Vec<Vec2> = (0..n)
+            .map(|_| Vec2::new(rng.gen_range(0.0..side), rng.gen_range(0.0..side)))
+            .collect();
+        let mut velocities: Vec<Vec2> = (0..n)
+            .map(|_| Vec2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)))
+            .collect();
+        let collision_cell = Vec2::splat(cell_size_for(boid_config.collision_radius(), None));
+        let clustering_cell = Vec2::splat(cell_size_for(boid_config.clustering_radius(), None));
+        let mut clustering = Clustering::with_hasher(clustering_cell, Default::default());
+        SpatialIndex::build(&mut clustering, &positions);
+        clustering.update_velocities(&velocities);
+
+        let (scattered, before) = run(&positions, &velocities, &clustering, &boid_config);
+
+        let order = morton_order(&positions, collision_cell, Vec2::ZERO);
+        reorder_by(&mut positions, &order);
+        reorder_by(&mut velocities, &order);
+        clustering.remap_ids(&order);
+        let (sorted, after) = run(&positions, &velocities, &clustering, &boid_config);
+
+        for (i, old) in order.iter().enumerate() {
+            assert!(after[i].abs_diff_eq(before[*old as usize], 1e-2));
+        }
+        println!(
+            "{} boids: scattered {:?}, morton {:?}, speed-up {:.2}x",
+            n,
+            scattered,
+            sorted,
+            scattered.as_secs_f64() / sorted.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use super::super::space::{SpaceVector, SpatialIndex};
+
+/// 分开重叠的圆（3D里是球），宽阶段的候选对来自碰撞层的 `pairs_within`
+/// 穿透深度按质量的倒数分给两边，接近中的一对再沿法向施加冲量
+/// `restitution` 为0时完全非弹性，为1时完全弹性；质量不大于0的实体视为不可推动
+/// 每次迭代都用最新的位置重新计算重叠，没有重叠时提前结束
+#[allow(clippy::too_many_arguments)]
+pub(super) fn resolve_collisions<V: SpaceVector, I: SpatialIndex<V> + ?Sized>(
+    space: &I,
+    candidates: &[(u32, u32, f32)],
+    positions: &mut [V],
+    velocities: &mut [V],
+    radii: &[f32],
+    masses: &[f32],
+    restitution: f32,
+    iterations: u32,
+) {
+    let inverse_mass = |i: usize| {
+        if masses[i] > 0. {
+            1. / masses[i]
+        } else {
+            0.
+        }
+    };
+    for _ in 0..iterations {
+        let mut overlapped = false;
+        for (a, b, _) in candidates {
+            let (a, b) = (*a as usize, *b as usize);
+            let diff = space.displacement(positions[a], positions[b]);
+            let dist = diff.length();
+            let min_dist = radii[a] + radii[b];
+            if dist >= min_dist {
+                continue;
+            }
+            let (inv_a, inv_b) = (inverse_mass(a), inverse_mass(b));
+            let inv_sum = inv_a + inv_b;
+            if inv_sum == 0. {
+                continue;
+            }
+            overlapped = true;
+            // 完全重合时没有方向，随便取一个
+            let normal = if dist > 0. {
+                diff / dist
+            } else {
+                V::splat(1.).normalize()
+            };
+            let correction = normal * ((min_dist - dist) / inv_sum);
+            positions[a] -= correction * inv_a;
+            positions[b] += correction * inv_b;
+
+            let approaching = (velocities[b] - velocities[a]).dot(normal);
+            if approaching < 0. {
+                let impulse = normal * (-(1. + restitution) * approaching / inv_sum);
+                velocities[a] -= impulse * inv_a;
+                velocities[b] += impulse * inv_b;
+            }
+        }
+        if !overlapped {
+            break;
+        }
+    }
+}
+
+#[test]
+fn head_on_collision_separates_and_bounces() {
+    use glam::Vec2;
+    let mut map =
+        super::super::space::Collision::with_hasher(Vec2::new(20., 20.), Default::default());
+    let mut positions = vec![Vec2::new(0., 0.), Vec2::new(8., 0.), Vec2::new(50., 0.)];
+    for (i, p) in positions.iter().enumerate() {
+        map.insert(i as u32, *p);
+    }
+    let candidates = SpatialIndex::pairs_within(&map, &positions, 10.);
+    assert_eq!(candidates.len(), 1);
+
+    // 质量比 1:3，完全弹性
+    let mut velocities = vec![Vec2::new(4., 0.), Vec2::new(-4., 0.), Vec2::ZERO];
+    let (radii, masses) = ([5., 5., 5.], [1., 3., 1.]);
+    resolve_collisions(
+        &map,
+        &candidates,
+        &mut positions,
+        &mut velocities,
+        &radii,
+        &masses,
+        1.,
+        4,
+    );
+    assert!((positions[1].x - positions[0].x - 10.).abs() < 1e-4);
+    // 轻的一边被推得更远
+    assert!((positions[0].x + 1.5).abs() < 1e-4);
+    assert!((positions[1].x - 8.5).abs() < 1e-4);
+    // 动量守恒，弹性碰撞后相对速度反向
+    let momentum = velocities[0] * masses[0] + velocities[1] * masses[1];
+    assert!(momentum.abs_diff_eq(Vec2::new(-8., 0.), 1e-4));
+    assert!((velocities[1] - velocities[0]).abs_diff_eq(Vec2::new(8., 0.), 1e-4));
+    assert_eq!(velocities[2], Vec2::ZERO);
+}
@@ -0,0 +1,331 @@
+mod camera;
+use camera::OrbitCamera;
+use glam::Vec3;
+use rand::Rng;
+use ready_paint::{
+    multi::{refs_muts, Mut, Ref},
+    scene::{get_res, return_res, Pass, Ready, Update},
+};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    boid::steer_all,
+    config::{BoidConfig, Config},
+    space::{cell_size_for, ClusteringMarker, SpaceMap3, SpatialIndex},
+};
+
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone)]
+pub struct _SphereInstance {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub velocity: [f32; 3],
+    _padding: f32,
+}
+
+/// 3D的boid场景，世界是 宽 x 高 x min(宽, 高) 的盒子，实体超出边界后从对面绕回
+/// 聚类层是环面的 `SpaceMap3`，绕回边界两边的实体互相可见，规则和2D共用 `steer_velocity`
+/// 和2D一样碰撞层还没有参与转向，所以这里只有聚类层
+#[derive(Default)]
+pub struct Boid3d {
+    positions: Vec<Vec3>,
+    velocities: Vec<Vec3>,
+    // 上一次写入空间时的位置
+    last_positions: Vec<Vec3>,
+    target: Vec3,
+    world_size: Vec3,
+    // 和2D一样在cell里存 `(id, 速度)`
+    clustering: Option<SpaceMap3<ClusteringMarker, (u32, Vec3)>>,
+    camera: Option<OrbitCamera>,
+    aspect: f32,
+    instance_collect: Vec<_SphereInstance>,
+    single_buffer: Option<wgpu::Buffer>,
+    instance_buffer: Option<wgpu::Buffer>,
+    camera_buffer: Option<wgpu::Buffer>,
+    camera_bind_group: Option<wgpu::BindGroup>,
+    pipeline: Option<wgpu::RenderPipeline>,
+    pub depth_texture: Option<wgpu::Texture>,
+}
+
+impl Ready for Boid3d {
+    fn ready(
+        &mut self,
+        data: &mut ready_paint::scene::HashTypeId2Data,
+        gfx: &ready_paint::gfx::Gfx,
+    ) {
+        let config = get_res::<Config>(data);
+        let surface_config = gfx.surface_config.as_ref().unwrap();
+        let (width, height) = (surface_config.width as f32, surface_config.height as f32);
+        let world_size = Vec3::new(width, height, width.min(height));
+        let mut rng = rand::thread_rng();
+        let max = config.entity_max_speed;
+        let positions: Vec<Vec3> = (0..config.max_entities)
+            .map(|_| {
+                Vec3::new(
+                    rng.gen_range(0.0..world_size.x),
+                    rng.gen_range(0.0..world_size.y),
+                    rng.gen_range(0.0..world_size.z),
+                )
+            })
+            .collect();
+        let velocities: Vec<Vec3> = positions
+            .iter()
+            .map(|_| {
+                Vec3::new(
+                    rng.gen_range(-max..max),
+                    rng.gen_range(-max..max),
+                    rng.gen_range(-max..max),
+                )
+            })
+            .collect();
+        let instance_collect: Vec<_SphereInstance> = positions
+            .iter()
+            .zip(velocities.iter())
+            .map(|(p, v)| _SphereInstance {
+                position: p.to_array(),
+                radius: config.entity_radius,
+                velocity: v.to_array(),
+                _padding: 0.,
+            })
+            .collect();
+        // 和2D的空间层一样从查询半径算cell大小，世界在 boundary_margin 外绕回
+        let boid_config = get_res::<BoidConfig>(data);
+        let margin = Vec3::splat(boid_config.boundary_margin);
+        let mut clustering = SpaceMap3::with_hasher(
+            Vec3::splat(cell_size_for(boid_config.clustering_radius(), None)),
+            Default::default(),
+        );
+        clustering.with_wrap(-margin, world_size + margin);
+        for (i, (p, v)) in positions.iter().zip(velocities.iter()).enumerate() {
+            clustering.insert((i as u32, *v), *p);
+        }
+
+        let single_buffer = gfx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("boid3d single buffer"),
+            contents: bytemuck::bytes_of(&[
+                [-1.0f32, -1.0f32],
+                [1.0f32, -1.0f32],
+                [-1.0f32, 1.0f32],
+                [1.0f32, 1.0f32],
+                [-1.0f32, 1.0f32],
+                [1.0f32, -1.0f32],
+            ]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let instance_buffer = gfx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("boid3d instance buffer"),
+            contents: bytemuck::cast_slice(instance_collect.as_slice()),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera = OrbitCamera::looking_at(world_size / 2., world_size);
+        let aspect = width / height;
+        let camera_buffer = gfx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("boid3d camera buffer"),
+            contents: bytemuck::bytes_of(&camera.uniforms(aspect)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group_layout =
+            gfx.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("boid3d camera bind group layout"),
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                });
+        let camera_bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("boid3d camera bind group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+        // resize之后会重新ready，深度图跟着窗口大小重建
+        let depth_texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("boid3d depth texture"),
+            size: wgpu::Extent3d {
+                width: surface_config.width,
+                height: surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let pipeline_layout = gfx
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("boid3d pipeline layout"),
+                bind_group_layouts: &[&camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let shader = gfx
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("boid3d flock"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("boid3d/flock.wgsl").into()),
+            });
+        let pipeline = gfx
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("boid3d render pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[
+                        wgpu::VertexBufferLayout {
+                            array_stride: 4 * 2,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &[wgpu::VertexAttribute {
+                                format: wgpu::VertexFormat::Float32x2,
+                                offset: 0,
+                                shader_location: 0,
+                            }],
+                        },
+                        wgpu::VertexBufferLayout {
+                            array_stride: std::mem::size_of::<_SphereInstance>()
+                                as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Instance,
+                            attributes: &[
+                                wgpu::VertexAttribute {
+                                    format: wgpu::VertexFormat::Float32x3,
+                                    offset: 0,
+                                    shader_location: 1,
+                                },
+                                wgpu::VertexAttribute {
+                                    format: wgpu::VertexFormat::Float32,
+                                    offset: 4 * 3,
+                                    shader_location: 2,
+                                },
+                                wgpu::VertexAttribute {
+                                    format: wgpu::VertexFormat::Float32x3,
+                                    offset: 4 * 4,
+                                    shader_location: 3,
+                                },
+                            ],
+                        },
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(surface_config.view_formats[0].into())],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            });
+
+        return_res(
+            data,
+            Boid3d {
+                last_positions: positions.clone(),
+                positions,
+                velocities,
+                target: world_size / 2.,
+                world_size,
+                clustering: Some(clustering),
+                camera: Some(camera),
+                aspect,
+                instance_collect,
+                single_buffer: Some(single_buffer),
+                instance_buffer: Some(instance_buffer),
+                camera_buffer: Some(camera_buffer),
+                camera_bind_group: Some(camera_bind_group),
+                pipeline: Some(pipeline),
+                depth_texture: Some(depth_texture),
+            },
+        );
+    }
+}
+
+impl Update for Boid3d {
+    fn update(data: &mut ready_paint::scene::HashTypeId2Data, gfx: &ready_paint::gfx::Gfx) {
+        let dt = gfx.delta_time;
+        let (boid, boid_config) = refs_muts::<(Mut<Boid3d>, Ref<BoidConfig>)>(data);
+        let clustering = boid.clustering.as_mut().unwrap();
+
+        // 只有跨cell的实体才会改动map
+        clustering.update_positions(&boid.last_positions, &boid.positions);
+        clustering.update_velocities(&boid.velocities);
+        boid.last_positions.copy_from_slice(&boid.positions);
+
+        let new_velocities = steer_all(
+            &boid.positions,
+            &boid.velocities,
+            boid.target,
+            boid.clustering.as_ref().unwrap(),
+            boid_config,
+            dt,
+        );
+
+        // 边界处理，每个轴分别在 boundary_margin 外绕回
+        let margin = Vec3::splat(boid_config.boundary_margin);
+        let (low, high) = (-margin, boid.world_size + margin);
+        for (i, new_velocity) in new_velocities.into_iter().enumerate() {
+            let mut position = boid.positions[i] + new_velocity * dt;
+            position = Vec3::select(position.cmplt(low), high, position);
+            position = Vec3::select(position.cmpgt(high), low, position);
+            boid.positions[i] = position;
+            boid.velocities[i] = new_velocity;
+            let instance = &mut boid.instance_collect[i];
+            instance.position = position.to_array();
+            instance.velocity = new_velocity.to_array();
+        }
+
+        let camera = boid.camera.as_mut().unwrap();
+        camera.advance(dt);
+        gfx.queue.write_buffer(
+            boid.camera_buffer.as_ref().unwrap(),
+            0,
+            bytemuck::bytes_of(&camera.uniforms(boid.aspect)),
+        );
+        gfx.queue.write_buffer(
+            boid.instance_buffer.as_ref().unwrap(),
+            0,
+            bytemuck::cast_slice(boid.instance_collect.as_slice()),
+        );
+    }
+}
+
+impl<'a> Pass<'a> for Boid3d {
+    fn pass(
+        data: &mut ready_paint::scene::HashTypeId2Data,
+        render_pass: &'a mut wgpu::RenderPass<'a>,
+    ) -> &'a mut wgpu::RenderPass<'a> {
+        let boid = get_res::<Boid3d>(data);
+        render_pass.set_pipeline(boid.pipeline.as_ref().unwrap());
+        render_pass.set_bind_group(0, boid.camera_bind_group.as_ref().unwrap(), &[]);
+        render_pass.set_vertex_buffer(0, boid.single_buffer.as_ref().unwrap().slice(..));
+        render_pass.set_vertex_buffer(1, boid.instance_buffer.as_ref().unwrap().slice(..));
+        render_pass.draw(0..6, 0..boid.instance_collect.len() as u32);
+        render_pass
+    }
+}
@@ -0,0 +1,53 @@
+use glam::{Mat4, Vec3};
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone)]
+pub struct _CameraUniforms {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+}
+
+/// 绕世界中心旋转的透视相机
+pub struct OrbitCamera {
+    pub center: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov_y: f32,
+    // 每秒转过的角度（弧度）
+    pub spin_speed: f32,
+}
+
+impl OrbitCamera {
+    /// 看向 `center`，距离足够把 `extent` 大小的盒子放进视野
+    pub fn looking_at(center: Vec3, extent: Vec3) -> Self {
+        OrbitCamera {
+            center,
+            distance: extent.length() * 0.9,
+            yaw: 0.,
+            pitch: 0.35,
+            fov_y: 60f32.to_radians(),
+            spin_speed: 0.15,
+        }
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        self.yaw = (self.yaw + self.spin_speed * dt) % std::f32::consts::TAU;
+    }
+
+    pub fn eye(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.center + Vec3::new(sin_yaw * cos_pitch, sin_pitch, cos_yaw * cos_pitch) * self.distance
+    }
+
+    pub fn uniforms(&self, aspect: f32) -> _CameraUniforms {
+        let view = Mat4::look_at_rh(self.eye(), self.center, Vec3::Y);
+        // 远平面留出世界盒子另一侧的余量
+        let proj = Mat4::perspective_rh(self.fov_y, aspect, 1., self.distance * 3.);
+        _CameraUniforms {
+            view: view.to_cols_array_2d(),
+            proj: proj.to_cols_array_2d(),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+struct Camera {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+}
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+struct VertexIn {
+    @location(0) v: vec2<f32>,
+}
+struct Instance {
+    @location(1) position: vec3<f32>,
+    @location(2) radius: f32,
+    @location(3) velocity: vec3<f32>,
+}
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) local_pos: vec2<f32>,
+    @location(1) color: vec3<f32>,
+}
+
+@vertex
+fn vs_main(input: VertexIn, instance: Instance) -> VertexOut {
+    var output: VertexOut;
+    // 在相机空间里展开成始终朝向相机的圆片
+    var view_pos = camera.view * vec4<f32>(instance.position, 1.0);
+    view_pos = vec4<f32>(view_pos.xy + input.v * instance.radius, view_pos.zw);
+    output.position = camera.proj * view_pos;
+    output.local_pos = input.v;
+    // 用速度方向上色，方便看出群体的朝向
+    let speed = length(instance.velocity);
+    var dir = vec3<f32>(0.0, 1.0, 0.0);
+    if (speed > 0.0) {
+        dir = instance.velocity / speed;
+    }
+    output.color = dir * 0.4 + vec3<f32>(0.5, 0.6, 0.5);
+    return output;
+}
+
+@fragment
+fn fs_main(input: VertexOut) -> @location(0) vec4<f32> {
+    let dist2 = dot(input.local_pos, input.local_pos);
+    if (dist2 > 1.0) {
+        discard;
+    }
+    // 假装是个球，简单的漫反射
+    let normal = vec3<f32>(input.local_pos, sqrt(1.0 - dist2));
+    let light = normalize(vec3<f32>(0.4, 0.6, 0.7));
+    let diffuse = max(dot(normal, light), 0.0) * 0.8 + 0.2;
+    return vec4<f32>(input.color * diffuse, 1.0);
+}
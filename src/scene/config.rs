@@ -1,9 +1,37 @@
 use glam::Vec2;
 use ready_paint::scene::Ready;
 
+/// 碰撞层和聚类层使用的空间索引
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpatialBackend {
+    /// `SpaceMap`，不限世界范围，开启 `wrap_space` 时为环面
+    Hash,
+    /// `DenseGrid`，覆盖窗口加上 `boundary_margin` 的范围
+    Dense,
+    /// `LooseQuadtree`，实体扎堆时自适应细分
+    Quadtree,
+}
+
 pub struct Config {
     pub max_entities: u32,
     pub entity_max_speed: f32,
+    // 定期打印hash构建耗时等诊断信息，默认关闭
+    pub debug_report: bool,
+    pub entity_radius: f32,
+    // 是否给对应的空间开启边界层
+    pub collision_border_layer: bool,
+    pub clustering_border_layer: bool,
+    // 两层可以分别选择空间索引
+    pub collision_backend: SpatialBackend,
+    pub clustering_backend: SpatialBackend,
+    // 世界在窗口加 boundary_margin 的范围上首尾相接，只有 SpaceMap 支持
+    pub wrap_space: bool,
+    // 运行时根据占用统计调整 SpaceMap 的cell大小
+    pub auto_tune_cells: bool,
+    // SpaceMap 每帧整体重建并保留cell的分配，连续空了这么多帧的cell才释放，None为增量更新
+    pub bucket_reuse_frames: Option<u32>,
+    // 每隔这么多帧按Z序重排一次实体数组，空间上相邻的boid在内存里也相邻，None为不重排
+    pub morton_reorder_frames: Option<u32>,
 }
 
 impl Ready for Config {
@@ -20,6 +48,16 @@ impl Default for Config {
         Config {
             max_entities: 1000,
             entity_max_speed: 10.,
+            debug_report: false,
+            entity_radius: 5.,
+            collision_border_layer: true,
+            clustering_border_layer: false,
+            collision_backend: SpatialBackend::Hash,
+            clustering_backend: SpatialBackend::Hash,
+            wrap_space: false,
+            auto_tune_cells: false,
+            bucket_reuse_frames: None,
+            morton_reorder_frames: None,
         }
     }
 }
@@ -54,6 +92,10 @@ pub struct BoidConfig {
 
     // 边界参数
     pub boundary_margin: f32,
+
+    // 碰撞参数
+    pub collision_restitution: f32, // 恢复系数，0为完全非弹性，1为完全弹性
+    pub collision_iterations: u32,  // 每帧分离重叠的迭代次数，0为关闭碰撞
 }
 
 impl Ready for BoidConfig {
@@ -64,6 +106,18 @@ impl Ready for BoidConfig {
     ) {
     }
 }
+impl BoidConfig {
+    /// 碰撞层上的查询半径
+    pub fn collision_radius(&self) -> f32 {
+        self.separation_radius
+    }
+
+    /// 聚类层上的查询半径，对齐和内聚共用一次查询
+    pub fn clustering_radius(&self) -> f32 {
+        self.alignment_max_radius.max(self.cohesion_radius)
+    }
+}
+
 impl Default for BoidConfig {
     fn default() -> Self {
         Self {
@@ -96,6 +150,10 @@ impl Default for BoidConfig {
 
             // 边界参数
             boundary_margin: 50.0,
+
+            // 碰撞参数
+            collision_restitution: 0.5,
+            collision_iterations: 0,
         }
     }
 }
@@ -1,5 +1,9 @@
-use super::config::Config;
+use super::{
+    config::Config,
+    space::{reorder_by, SpatialIndex},
+};
 use glam::Vec2;
+use handle::{EntityHandle, HandleAllocator, UnknownEntityId};
 use instance::_CircleInstance;
 use noise::NoiseFn;
 use rand::Rng;
@@ -15,6 +19,8 @@ pub struct Entity<'a> {
     vertex_layout: Option<wgpu::VertexBufferLayout<'a>>,
     instance_layout: Option<wgpu::VertexBufferLayout<'a>>,
     pub instance_collect: Option<Vec<_CircleInstance>>,
+    // 实体数组按下标紧凑存放，外部用句柄跨帧引用某个实体
+    handles: HandleAllocator,
 }
 
 impl<'a> Ready for Entity<'a> {
@@ -62,7 +68,7 @@ impl<'a> Ready for Entity<'a> {
             .iter()
             .map(|e| _CircleInstance {
                 position: e.to_array(),
-                radius: 5.,
+                radius: config.entity_radius,
                 velocity: [rng.gen_range(-max..max), rng.gen_range(-max..max)],
             })
             .collect::<Vec<_CircleInstance>>();
@@ -110,11 +116,129 @@ impl<'a> Ready for Entity<'a> {
                 entity_poses: Some(entity_poses),
                 vertex_layout: Some(vertex_layout),
                 instance_layout: Some(instance_layout),
+                handles: HandleAllocator::with_len(instance_collect.len()),
                 instance_collect: Some(instance_collect),
             },
         );
     }
 }
+impl<'a> Entity<'a> {
+    /// 下标为 `index` 的实体的句柄，空间索引里的id就是这个下标
+    pub fn handle_at(&self, index: usize) -> EntityHandle {
+        self.handles.handle_at(index)
+    }
+
+    /// 句柄对应的实体现在的下标，实体已经被删除时返回 `None`
+    pub fn index_of(&self, handle: EntityHandle) -> Option<usize> {
+        self.handles.index_of(handle)
+    }
+
+    pub fn is_alive(&self, handle: EntityHandle) -> bool {
+        self.handles.is_alive(handle)
+    }
+
+    pub fn instance(&self, handle: EntityHandle) -> Option<&_CircleInstance> {
+        let index = self.index_of(handle)?;
+        self.instance_collect.as_ref()?.get(index)
+    }
+
+    /// 空间索引返回的id换成句柄，有id不对应任何实体时返回错误
+    /// 空间的各层只存紧凑下标，句柄只在 `Entity` 这一层出现
+    /// 下标在删除和重排之后会换成别的实体，要跨帧保存的结果先换成句柄
+    /// `nearest_k`、`cells` 这些只有 `SpaceMap` 才有的查询也用它转换
+    pub fn handles(
+        &self,
+        ids: impl IntoIterator<Item = u32>,
+    ) -> Result<Vec<EntityHandle>, UnknownEntityId> {
+        ids.into_iter().map(|id| self.handle_of(id)).collect()
+    }
+
+    fn handle_of(&self, id: u32) -> Result<EntityHandle, UnknownEntityId> {
+        if (id as usize) < self.handles.len() {
+            Ok(self.handle_at(id as usize))
+        } else {
+            Err(UnknownEntityId(id))
+        }
+    }
+
+    /// 空间里 `pos` 周围 `r` 以内的实体
+    pub fn query_radius<I: SpatialIndex + ?Sized>(
+        &self,
+        space: &I,
+        pos: Vec2,
+        r: f32,
+    ) -> Result<Vec<EntityHandle>, UnknownEntityId> {
+        self.handles(space.query_radius(pos, r))
+    }
+
+    pub fn query_aabb<I: SpatialIndex + ?Sized>(
+        &self,
+        space: &I,
+        min: Vec2,
+        max: Vec2,
+    ) -> Result<Vec<EntityHandle>, UnknownEntityId> {
+        self.handles(space.query_aabb(min, max))
+    }
+
+    pub fn nearest<I: SpatialIndex + ?Sized>(
+        &self,
+        space: &I,
+        pos: Vec2,
+    ) -> Result<Option<EntityHandle>, UnknownEntityId> {
+        space.nearest(pos).map(|id| self.handle_of(id)).transpose()
+    }
+
+    /// 距离不超过 `dist` 的实体对，位置用上一次写入空间的 `entity_poses`
+    pub fn pairs_within<I: SpatialIndex + ?Sized>(
+        &self,
+        space: &I,
+        dist: f32,
+    ) -> Result<Vec<(EntityHandle, EntityHandle, f32)>, UnknownEntityId> {
+        let Some(poses) = self.entity_poses.as_ref() else {
+            return Ok(Vec::new());
+        };
+        space
+            .pairs_within(poses, dist)
+            .into_iter()
+            .map(|(a, b, d)| Ok((self.handle_of(a)?, self.handle_of(b)?, d)))
+            .collect()
+    }
+
+    /// 按 `order` 重排实体数组，新下标 `i` 放原来的实体 `order[i]`，句柄跟着实体走
+    pub(super) fn permute(&mut self, order: &[u32]) {
+        if let Some(instances) = self.instance_collect.as_mut() {
+            reorder_by(instances, order);
+        }
+        if let Some(poses) = self.entity_poses.as_mut() {
+            reorder_by(poses, order);
+        }
+        self.handles.permute(order);
+    }
+
+    /// 新实体放到数组末尾，还没有写入空间
+    pub(super) fn spawn(&mut self, instance: _CircleInstance) -> EntityHandle {
+        let position = Vec2::from_array(instance.position);
+        self.instance_collect
+            .get_or_insert_with(Vec::new)
+            .push(instance);
+        self.entity_poses
+            .get_or_insert_with(Vec::new)
+            .push(position);
+        self.handles.allocate()
+    }
+
+    /// 删除实体，最后一个实体挪到空出来的下标，返回被删实体原来的下标
+    pub(super) fn despawn(&mut self, handle: EntityHandle) -> Option<usize> {
+        let index = self.handles.release(handle)?;
+        if let Some(instances) = self.instance_collect.as_mut() {
+            instances.swap_remove(index);
+        }
+        if let Some(poses) = self.entity_poses.as_mut() {
+            poses.swap_remove(index);
+        }
+        Some(index)
+    }
+}
 impl<'a> Pass<'a> for Entity<'a> {
     fn pass(
         data: &mut ready_paint::scene::HashTypeId2Data,
@@ -128,6 +252,7 @@ impl<'a> Pass<'a> for Entity<'a> {
         render_pass
     }
 }
+pub mod handle;
 pub mod instance;
 pub mod share;
 pub mod single;
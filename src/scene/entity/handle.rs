@@ -0,0 +1,195 @@
+use std::fmt;
+
+use super::super::space::{reorder_by, Payload};
+
+/// 实体的稳定引用：槽位下标加代数
+/// 实体被删除后槽位的代数加一，之后再分配出去旧句柄也对不上，查询时会被拒绝
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntityHandle {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityHandle {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// 句柄可以直接存进 `SpaceMap`，id是槽位下标
+impl Payload for EntityHandle {
+    fn id(&self) -> u32 {
+        self.index
+    }
+}
+
+/// 空间索引给出的id不对应任何实体，说明索引和实体数组已经不同步
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownEntityId(pub u32);
+
+impl fmt::Display for UnknownEntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entity id {} is out of range", self.0)
+    }
+}
+
+impl std::error::Error for UnknownEntityId {}
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    generation: u32,
+    // 存活时实体在紧凑数组里的下标
+    dense: Option<u32>,
+}
+
+/// 分配实体句柄，删除后空出来的槽位会被重新使用
+/// 实体数据按下标紧凑存放，删除时最后一个实体挪到空出来的下标，它的句柄不变
+#[derive(Default)]
+pub struct HandleAllocator {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    // 紧凑下标 -> 句柄
+    handles: Vec<EntityHandle>,
+}
+
+impl HandleAllocator {
+    /// 给已经存在的 `len` 个实体分配句柄，第 `i` 个实体的槽位就是 `i`
+    pub fn with_len(len: usize) -> Self {
+        let mut allocator = HandleAllocator::default();
+        for _ in 0..len {
+            allocator.allocate();
+        }
+        allocator
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// 新实体的句柄，实体放在紧凑数组的末尾
+    pub fn allocate(&mut self) -> EntityHandle {
+        let dense = Some(self.handles.len() as u32);
+        let handle = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.dense = dense;
+                EntityHandle {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    dense,
+                });
+                EntityHandle {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.handles.push(handle);
+        handle
+    }
+
+    /// 释放句柄，返回实体原来的下标，调用方对同一个下标做 `swap_remove`
+    /// 句柄已经失效时返回 `None`
+    pub fn release(&mut self, handle: EntityHandle) -> Option<usize> {
+        let index = self.index_of(handle)?;
+        self.handles.swap_remove(index);
+        if let Some(moved) = self.handles.get(index) {
+            self.slots[moved.index as usize].dense = Some(index as u32);
+        }
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.dense = None;
+        self.free.push(handle.index);
+        Some(index)
+    }
+
+    /// 句柄对应的实体现在的下标，失效的句柄返回 `None`
+    pub fn index_of(&self, handle: EntityHandle) -> Option<usize> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.dense.map(|dense| dense as usize)
+    }
+
+    pub fn is_alive(&self, handle: EntityHandle) -> bool {
+        self.index_of(handle).is_some()
+    }
+
+    /// 实体数组按 `order` 重排之后更新映射，新下标 `i` 原来是 `order[i]`，句柄都不变
+    pub fn permute(&mut self, order: &[u32]) {
+        reorder_by(&mut self.handles, order);
+        for (index, handle) in self.handles.iter().enumerate() {
+            self.slots[handle.index as usize].dense = Some(index as u32);
+        }
+    }
+
+    /// 下标为 `index` 的实体的句柄
+    pub fn handle_at(&self, index: usize) -> EntityHandle {
+        self.handles[index]
+    }
+}
+
+#[test]
+fn released_slots_are_reused_with_new_generation() {
+    let mut handles = HandleAllocator::with_len(3);
+    let (a, b, c) = (
+        handles.handle_at(0),
+        handles.handle_at(1),
+        handles.handle_at(2),
+    );
+
+    // 删掉中间的实体，最后一个实体挪到下标1
+    assert_eq!(handles.release(b), Some(1));
+    assert_eq!(handles.index_of(c), Some(1));
+    assert_eq!(handles.index_of(a), Some(0));
+    assert!(!handles.is_alive(b));
+    assert_eq!(handles.release(b), None);
+
+    // 新实体复用b的槽位，但旧句柄仍然无效
+    let d = handles.allocate();
+    assert_eq!(d.index(), b.index());
+    assert_ne!(d.generation(), b.generation());
+    assert_eq!(handles.index_of(d), Some(2));
+    assert_eq!(handles.index_of(b), None);
+    assert_eq!(handles.len(), 3);
+}
+
+#[test]
+fn space_map_rejects_stale_handles() {
+    use super::super::space::{CollisionMarker, SpaceMap};
+    use glam::Vec2;
+    use std::hash::RandomState;
+    let mut map = SpaceMap::<CollisionMarker, RandomState, EntityHandle>::with_hasher(
+        Vec2::new(10., 10.),
+        RandomState::new(),
+    );
+    let mut handles = HandleAllocator::default();
+    let a = handles.allocate();
+    map.insert(a, Vec2::new(3., 3.));
+    assert_eq!(map.position(a), Some(Vec2::new(3., 3.)));
+
+    map.remove(a.id());
+    handles.release(a);
+    let b = handles.allocate();
+    map.insert(b, Vec2::new(4., 4.));
+    assert_eq!(b.id(), a.id());
+    assert_eq!(map.position(a), None);
+    assert_eq!(map.position(b), Some(Vec2::new(4., 4.)));
+
+    let mut found = Vec::new();
+    map.for_each_payload_in_radius(Vec2::ZERO, 10., |handle, _| found.push(*handle));
+    assert_eq!(found, vec![b]);
+}
@@ -1,44 +1,101 @@
+mod border;
+mod cells;
+pub mod dense;
+mod dim;
 pub mod draw;
+mod extent;
+mod index;
+mod level;
+mod morton;
+mod nearest;
+mod pairs;
+#[cfg(feature = "rayon")]
+mod par;
+pub mod quadtree;
+mod ray;
+mod reuse;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+mod stats;
+mod tune;
 mod unit;
-use glam::{IVec2, Vec2};
-use ready_paint::scene::{return_res, Ready};
-use std::{
-    collections::HashMap,
-    fmt::{write, Display},
-    marker::PhantomData,
-};
-use unit::{IndexGrid, PosString};
+mod wrap;
+use super::config::{BoidConfig, Config, SpatialBackend};
+use dense::DenseGrid;
+use glam::{Vec2, Vec3};
+use quadtree::LooseQuadtree;
+use ready_paint::scene::{get_res, return_res, Ready};
+use std::{collections::HashMap, hash::BuildHasher, marker::PhantomData};
+use unit::{BorderKey, CellBuildHasher, EntitySlot, IndexGrid};
+pub use unit::{IndexPayload, Payload};
 
 #[derive(Default)]
 pub struct CollisionMarker;
 #[derive(Default)]
 pub struct ClusteringMarker;
 pub type Collision = SpaceMap<CollisionMarker>;
-pub type Clustering = SpaceMap<ClusteringMarker>;
+/// 聚类层的cell里存 `(id, 速度)`，对齐力不用再回到速度数组里取
+pub type Clustering = SpaceMap<ClusteringMarker, CellBuildHasher, (u32, Vec2)>;
+/// 3D的 `SpaceMap`，boid3d场景用
+pub type SpaceMap3<T, P = u32> = SpaceMap<T, CellBuildHasher, P, Vec3>;
+pub use dim::{SpaceCell, SpaceVector};
+pub use index::SpatialIndex;
+pub use level::SpaceLevel;
+pub use morton::{morton_order, reorder_by};
+pub use stats::SpaceStats;
+pub use tune::{cell_size_for, CellTuning};
+
 #[derive(Default)]
 pub struct Space {
     // 如果多个空间cellsize去处理不同的大小的空间划分
     // 每次的update的hash取值也是可以在同一个大对象处理
-    pub maps: Box<(Collision, Clustering)>,
+    // 按注册顺序保存，每一层用marker类型区分，也带一个名字方便调试
+    levels: Vec<level::LevelEntry>,
+    // 距离上一次检查占用统计过了多少帧
+    tune_frames: u32,
 }
 
+/// `P` 是每个点实体在cell里存的内容，默认只存id
+/// `V` 是位置向量，默认2D，边界层、射线和Z序重排只有2D有
 #[derive(Default)]
-pub struct SpaceMap<T> {
-    cell_size: Vec2,
-    map: HashMap<String, IndexGrid>,
-    _marker: PhantomData<T>,
-    border_layer_map: Option<HashMap<String, IndexGrid>>,
+pub struct SpaceMap<T, S = CellBuildHasher, P = u32, V: SpaceVector = Vec2> {
+    cell_size: V,
+    map: HashMap<V::Cell, IndexGrid<V, P>, S>,
+    // 主层中的实体总数，最近邻搜索用它判断是否已经看完所有实体
+    entity_count: usize,
+    // 按实体id索引，记住每个实体当前在哪个cell，增量更新时不用重建整个map
+    entity_slots: Vec<Option<EntitySlot<V::Cell>>>,
+    // 有大小的实体（圆/矩形）单独存放，每个cell记录覆盖到它的实体id
+    extent_map: HashMap<V::Cell, Vec<u32>, S>,
+    extents: Vec<Option<extent::Extent<V>>>,
+    // 只用来区分层，不影响 Send/Sync
+    _marker: PhantomData<fn() -> T>,
+    border_layer_map: Option<HashMap<BorderKey, IndexGrid, S>>,
     border_line_width: f32,
     x_entry: f32,
     y_entry: f32,
+    // 环面世界的范围，`None` 表示无限平面
+    wrap: Option<wrap::Wrap<V>>,
+    // 开启后每帧整体重建并保留cell的分配，cell连续空了这么多次重建才释放
+    evict_after: Option<u32>,
+    // 并行重建时 (cell, id, 位置) 的排序缓冲，跨帧复用
+    #[cfg(feature = "rayon")]
+    keyed: Vec<(V::Cell, u32, V)>,
 }
 
-#[derive(Debug)]
+/// 实体在cell中靠近的边或角，y轴正方向为上（T）
+/// 四个角之外也记录上下左右四条边（N/S/E/W）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BorderDir {
     LT,
     RT,
     LB,
     RB,
+    L,
+    R,
+    T,
+    B,
 }
 impl std::fmt::Display for BorderDir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -47,6 +104,10 @@ impl std::fmt::Display for BorderDir {
             BorderDir::RT => "RT".to_owned(),
             BorderDir::LB => "LB".to_owned(),
             BorderDir::RB => "RB".to_owned(),
+            BorderDir::L => "L".to_owned(),
+            BorderDir::R => "R".to_owned(),
+            BorderDir::T => "T".to_owned(),
+            BorderDir::B => "B".to_owned(),
         };
         write!(f, "{}", text)
     }
@@ -57,20 +118,73 @@ fn display_right() {
     assert_eq!(BorderDir::LB.to_string(), "LB");
 }
 
-impl<T> SpaceMap<T> {
-    fn new(cell_size: Vec2) -> Self {
+impl<T, S: BuildHasher + Clone + Default, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    fn new(cell_size: V) -> Self {
+        Self::with_hasher(cell_size, S::default())
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 指定hasher创建，默认的 `CellBuildHasher` 不够用时可以换掉
+    pub fn with_hasher(cell_size: V, hasher: S) -> Self {
         Self {
             cell_size,
-            map: HashMap::new(),
+            map: HashMap::with_hasher(hasher.clone()),
+            entity_count: 0,
+            entity_slots: Vec::new(),
+            extent_map: HashMap::with_hasher(hasher),
+            extents: Vec::new(),
             _marker: std::marker::PhantomData,
             border_layer_map: None,
             border_line_width: 0.,
             x_entry: 0.,
             y_entry: 0.,
+            wrap: None,
+            evict_after: None,
+            #[cfg(feature = "rayon")]
+            keyed: Vec::new(),
         }
     }
+    /// 移除所有实体，开启 `with_bucket_reuse` 时cell只清空不释放
     pub fn clear(&mut self) {
-        self.map.clear();
+        match self.evict_after {
+            Some(evict_after) => self.truncate_buckets(evict_after),
+            None => self.drop_buckets(),
+        }
+        self.entity_count = 0;
+        self.entity_slots.clear();
+        self.extents.clear();
+    }
+
+    /// 清空后用 `configure` 修改网格参数，再把已有的实体按新的网格插回去
+    fn rebuild_with(&mut self, configure: impl FnOnce(&mut Self)) {
+        let points: Vec<(P, V)> = self
+            .map
+            .values()
+            .flat_map(|grid| {
+                grid.payloads
+                    .iter()
+                    .copied()
+                    .zip(grid.positions.iter().copied())
+            })
+            .collect();
+        let extents: Vec<(u32, extent::Extent<V>)> = self
+            .extents
+            .iter()
+            .enumerate()
+            .filter_map(|(id, extent)| Some((id as u32, (*extent)?)))
+            .collect();
+        // cell大小或原点会变，旧的cell不能留下
+        self.clear();
+        self.drop_buckets();
+        configure(self);
+        self.update_border_entry();
+        for (payload, position) in points {
+            self.insert(payload, position);
+        }
+        for (id, extent) in extents {
+            self.insert_extent(id, extent);
+        }
     }
     // fn check_close_border(
     //     &self,
@@ -80,75 +194,473 @@ impl<T> SpaceMap<T> {
     // ) -> Option<IndexGrid> {
     //     border_map.entry(key)
     // }
-    pub fn insert(&mut self, entity_id: u32, position: Vec2) {
+    /// 插入实体，默认的payload就是实体id
+    /// 实体不能已经在map里（已存在的用 `update_position`）
+    pub fn insert(&mut self, payload: P, position: V) {
+        let entity_id = payload.id();
+        let position = self.wrap_position(position);
         let cell_pos = self.get_cell_index(position);
-        let key = PosString::from(cell_pos).value;
-        let cell_center = &self.get_cell_center(&cell_pos);
-        if let Some(border_map) = self.border_layer_map.as_mut() {
-            // if let Some(border_grid) = self.check_close_border(position, border_map, cell_center) {}
-            let dis = position - cell_center;
-            if dis.x.abs() > self.x_entry && dis.y.abs() > self.y_entry {
-                let border_dir = if dis.x > 0. && dis.y > 0. {
-                    BorderDir::RT
-                } else if dis.x < 0. && dis.y < 0. {
-                    BorderDir::LB
-                } else if dis.x < 0. && dis.y > 0. {
-                    BorderDir::LT
-                } else {
-                    BorderDir::RB
-                };
-                let pos_string = PosString::from((cell_pos, border_dir));
-                border_map
-                    .entry(pos_string.value)
-                    .or_insert(IndexGrid::new())
-                    .insert(entity_id);
-            }
-        }
-        self.map
-            .entry(key)
-            .or_insert(IndexGrid::new())
-            .insert(entity_id);
-    }
-
-    fn get_index_grid_by_pos(&self, grid_pos: &IVec2) -> Option<&IndexGrid> {
-        self.map.get(&PosString::from(grid_pos.clone()).value)
+        self.insert_border(entity_id, cell_pos, position);
+        let grid = self.map.entry(cell_pos).or_insert(IndexGrid::new());
+        grid.insert(payload, position);
+        let slot = EntitySlot {
+            cell: cell_pos,
+            index: grid.payloads.len() - 1,
+        };
+        let id = entity_id as usize;
+        if id >= self.entity_slots.len() {
+            self.entity_slots.resize(id + 1, None);
+        }
+        self.entity_slots[id] = Some(slot);
+        self.entity_count += 1;
+    }
+
+    /// 移除实体，所在的cell从实体记录的slot里取，有大小的实体从它覆盖的所有cell中移除
+    /// 返回实体之前是否在map里
+    pub fn remove(&mut self, entity_id: u32) -> bool {
+        let Some(slot) = self
+            .entity_slots
+            .get_mut(entity_id as usize)
+            .and_then(Option::take)
+        else {
+            return self.remove_extent(entity_id).is_some();
+        };
+        // 空的cell保留在map里，实体来回穿越时不用反复分配
+        let grid = self.map.get_mut(&slot.cell).unwrap();
+        let stored_position = grid.positions[slot.index];
+        if let Some(moved_id) = grid.swap_remove(slot.index) {
+            self.entity_slots[moved_id as usize].as_mut().unwrap().index = slot.index;
+        }
+        self.remove_border(entity_id, slot.cell, stored_position);
+        self.entity_count -= 1;
+        true
+    }
+
+    /// 实体从 `old` 移动到 `new`，只有跨cell时才会改动map的结构
+    /// 同一个cell内只刷新存储的位置，没在map里的实体直接插入
+    /// 有大小的实体整体平移 `new - old`
+    /// 存的payload同时换成 `payload`，每帧都在变的数据（比如速度）跟着位置一起刷新
+    pub fn update_position(&mut self, payload: P, old: V, new: V) {
+        let entity_id = payload.id();
+        let Some(slot) = self.entity_slots.get(entity_id as usize).copied().flatten() else {
+            if !self.translate_extent(entity_id, new - old) {
+                self.insert(payload, new);
+            }
+            return;
+        };
+        debug_assert_eq!(slot.cell, self.get_cell_index(old));
+        let new = self.wrap_position(new);
+        if self.get_cell_index(new) != slot.cell {
+            self.remove(entity_id);
+            self.insert(payload, new);
+            return;
+        }
+        let grid = self.map.get_mut(&slot.cell).unwrap();
+        grid.payloads[slot.index] = payload;
+        let stored_position = std::mem::replace(&mut grid.positions[slot.index], new);
+        if self.border_layer_map.is_some() {
+            self.remove_border(entity_id, slot.cell, stored_position);
+            self.insert_border(entity_id, slot.cell, new);
+        }
+    }
+
+    /// 点实体存储的位置，存的payload和 `payload` 不一样时返回 `None`
+    /// payload是带代数的句柄时，id被复用后旧句柄查不到新实体
+    pub fn position(&self, payload: P) -> Option<V>
+    where
+        P: PartialEq,
+    {
+        let slot = self.entity_slots.get(payload.id() as usize)?.as_ref()?;
+        let grid = self.map.get(&slot.cell)?;
+        (grid.payloads[slot.index] == payload).then(|| grid.positions[slot.index])
+    }
+
+    /// 把 `velocities[id]` 写进存了速度的payload，每帧在位置更新之后调用
+    /// 只存id的map什么都不做
+    pub fn update_velocities(&mut self, velocities: &[V])
+    where
+        P: IndexPayload<V>,
+    {
+        if !P::HAS_VELOCITY {
+            return;
+        }
+        for grid in self.map.values_mut() {
+            for payload in grid.payloads.iter_mut() {
+                if let Some(velocity) = velocities.get(payload.id() as usize) {
+                    payload.set_velocity(*velocity);
+                }
+            }
+        }
+    }
+
+    /// 按cell坐标取 `IndexGrid`
+    pub fn get_index_grid_by_pos(&self, grid_pos: &V::Cell) -> Option<&IndexGrid<V, P>> {
+        self.map.get(grid_pos)
     }
 
     /// 查询某个位置的cell
-    pub fn query(&self, entity_pos: Vec2) -> Option<&IndexGrid> {
+    pub fn query(&self, entity_pos: V) -> Option<&IndexGrid<V, P>> {
         let index_pos = self.get_cell_index(entity_pos);
-        self.map.get(&PosString::from(index_pos).value)
+        self.map.get(&index_pos)
     }
 
-    /// 根据位置返回cell的索引
-    fn get_cell_index(&self, position: Vec2) -> IVec2 {
-        IVec2::new(
-            (position.x / self.cell_size.x).floor() as i32,
-            (position.y / self.cell_size.y).floor() as i32,
-        )
+    /// 查询圆形范围内的实体，会访问所有和圆相交的cell
+    /// 只返回存储位置到 `pos` 距离不超过 `r` 的id，`r` 比cell大也可以
+    /// 开启边界层且 `r` 不超过边界带宽度时，只看边界层给出的邻居cell
+    /// 有大小的实体只要形状和圆相交就返回
+    pub fn query_radius(&self, pos: V, r: f32) -> Vec<u32> {
+        let mut result = Vec::new();
+        self.for_each_in_radius(pos, r, |id, _| result.push(id));
+        result
     }
 
-    fn get_cell_center(&self, cell_pos: &IVec2) -> Vec2 {
-        Vec2::new(
-            cell_pos.x as f32 * self.cell_size.x + self.cell_size.x / 2.,
-            cell_pos.y as f32 * self.cell_size.y + self.cell_size.y / 2.,
-        )
+    /// 对圆形范围内的每个实体调用 `f(id, offset)`，`offset` 是从 `pos` 指向实体的向量
+    /// 和 `query_radius` 的范围一样，但不分配结果数组
+    pub fn for_each_in_radius(&self, pos: V, r: f32, mut f: impl FnMut(u32, V)) {
+        self.for_each_extent_in_radius(pos, r, &mut f);
+        self.for_each_payload_in_radius(pos, r, |payload, offset| f(payload.id(), offset));
     }
 
-    fn set_index_grid_entities(&mut self, grid_pos: &IVec2, entity_ids: Vec<u32>) {
-        let key = PosString::from(grid_pos.clone()).value;
-        self.map.insert(key, IndexGrid { entity_ids });
+    /// `for_each_in_radius` 中有大小的那部分实体
+    fn for_each_extent_in_radius(&self, pos: V, r: f32, mut f: impl FnMut(u32, V)) {
+        let pos = self.wrap_position(pos);
+        let r2 = r * r;
+        let min_cell = self.raw_cell_index(pos - V::splat(r));
+        let max_cell = self.raw_cell_index(pos + V::splat(r));
+        self.for_each_extent(min_cell, max_cell, |id, extent| {
+            if self.extent_distance_squared(extent, pos) <= r2 {
+                f(id, self.displacement(pos, extent.center()));
+            }
+        });
     }
 
-    // add border layer
-    // the obj radius and distance for calculating to 2 * r + dis,
-    // keep a full enough distance to
-    fn with_border_layer(&mut self, object_radius: f32, object_center_separate_dis: f32) {
-        let border_line_width = object_center_separate_dis + 2. * object_radius;
-        self.border_layer_map = Some(HashMap::new());
-        self.border_line_width = border_line_width;
-        self.x_entry = self.cell_size.x - border_line_width;
-        self.y_entry = self.cell_size.y - border_line_width;
+    /// 和 `for_each_in_radius` 一样，但给出点实体存的payload，有大小的实体不在这里
+    pub fn for_each_payload_in_radius(&self, pos: V, r: f32, mut f: impl FnMut(&P, V)) {
+        let pos = self.wrap_position(pos);
+        let r2 = r * r;
+        let min_cell = self.raw_cell_index(pos - V::splat(r));
+        let max_cell = self.raw_cell_index(pos + V::splat(r));
+        let mut visit_within = |grid: &IndexGrid<V, P>| {
+            for (payload, p) in grid.payloads.iter().zip(grid.positions.iter()) {
+                let offset = self.displacement(pos, *p);
+                if offset.length_squared() <= r2 {
+                    f(payload, offset);
+                }
+            }
+        };
+        if self.border_covers(r) {
+            for cell_pos in self.border_neighbor_cells(pos) {
+                if let Some(grid) = self.map.get(&cell_pos) {
+                    visit_within(grid);
+                }
+            }
+            return;
+        }
+        let half_cell = self.cell_size / 2.;
+        self.for_each_cell(min_cell, max_cell, |raw_cell, cell_pos| {
+            // 圆和cell矩形不相交的直接跳过
+            let cell_center = self.get_cell_center(&raw_cell);
+            let gap = (self.displacement(pos, cell_center).abs() - half_cell).max(V::ZERO);
+            if gap.length_squared() > r2 {
+                return;
+            }
+            if let Some(grid) = self.map.get(&cell_pos) {
+                visit_within(grid);
+            }
+        });
+    }
+
+    /// 查询轴对齐矩形 `[min, max]`（含边界）内的实体
+    /// 点实体只存在于一个cell中，有大小的实体在重叠部分的左下角cell报告，所以结果不会重复
+    pub fn query_aabb(&self, min: V, max: V) -> Vec<u32> {
+        let mut result = Vec::new();
+        let min_cell = self.raw_cell_index(min);
+        let max_cell = self.raw_cell_index(max);
+        self.for_each_extent(min_cell, max_cell, |id, extent| {
+            if self.extent_intersects_aabb(extent, min, max) {
+                result.push(id);
+            }
+        });
+        self.for_each_cell(min_cell, max_cell, |_, cell_pos| {
+            let Some(grid) = self.map.get(&cell_pos) else {
+                return;
+            };
+            for (payload, p) in grid.payloads.iter().zip(grid.positions.iter()) {
+                if self.aabb_contains(min, max, *p) {
+                    result.push(payload.id());
+                }
+            }
+        });
+        result
+    }
+
+    /// 根据位置返回cell的索引，环面世界里已经取过模
+    fn get_cell_index(&self, position: V) -> V::Cell {
+        self.wrap_cell(self.raw_cell_index(position))
+    }
+
+    /// 未取模的cell索引，范围查询用它确定要扫的矩形
+    fn raw_cell_index(&self, position: V) -> V::Cell {
+        (position - self.cell_origin()).cell(self.cell_size)
+    }
+
+    fn get_cell_center(&self, cell_pos: &V::Cell) -> V {
+        self.cell_origin() + V::cell_min(*cell_pos, self.cell_size) + self.cell_size / 2.
+    }
+}
+
+#[test]
+fn query_radius_crosses_cell_border() {
+    let mut map = Collision::new(Vec2::new(100., 100.));
+    map.insert(0, Vec2::new(99., 50.));
+    map.insert(1, Vec2::new(101., 50.));
+    map.insert(2, Vec2::new(150., 50.));
+    map.insert(3, Vec2::new(-1., 50.));
+
+    let mut ids = map.query_radius(Vec2::new(99.5, 50.), 5.);
+    ids.sort();
+    assert_eq!(ids, vec![0, 1]);
+    // 单cell查询看不到隔壁cell的邻居
+    assert_eq!(
+        map.query(Vec2::new(99.5, 50.)).unwrap().get_entities(),
+        &[0]
+    );
+}
+
+#[test]
+fn query_radius_larger_than_cell() {
+    let mut map = Collision::new(Vec2::new(10., 10.));
+    let mut expected = Vec::new();
+    let center = Vec2::new(3., -7.);
+    let mut id = 0;
+    for y in -20..20 {
+        for x in -20..20 {
+            let p = Vec2::new(x as f32 * 4.3, y as f32 * 4.3);
+            map.insert(id, p);
+            if p.distance(center) <= 35. {
+                expected.push(id);
+            }
+            id += 1;
+        }
+    }
+    let mut ids = map.query_radius(center, 35.);
+    ids.sort();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn query_aabb_negative_coordinates() {
+    let mut map = Collision::new(Vec2::new(50., 50.));
+    map.insert(0, Vec2::new(-10., -10.));
+    map.insert(1, Vec2::new(-60., -1.));
+    map.insert(2, Vec2::new(-49., -51.));
+    map.insert(3, Vec2::new(1., 1.));
+    map.insert(4, Vec2::new(-101., -10.));
+
+    let mut ids = map.query_aabb(Vec2::new(-100., -60.), Vec2::new(0., 0.));
+    ids.sort();
+    assert_eq!(ids, vec![0, 1, 2]);
+}
+
+#[test]
+fn query_aabb_spans_many_cells() {
+    let mut map = Clustering::new(Vec2::new(7., 7.));
+    let min = Vec2::new(-33., -12.5);
+    let max = Vec2::new(41., 60.);
+    let mut expected = Vec::new();
+    let mut id = 0;
+    for y in -30..30 {
+        for x in -30..30 {
+            let p = Vec2::new(x as f32 * 2.5, y as f32 * 2.5);
+            map.insert((id, Vec2::ZERO), p);
+            if p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y {
+                expected.push(id);
+            }
+            id += 1;
+        }
+    }
+    let mut ids = map.query_aabb(min, max);
+    ids.sort();
+    let len = ids.len();
+    ids.dedup();
+    assert_eq!(ids.len(), len);
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn incremental_update_matches_rebuild() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(6);
+    let mut map = Collision::new(Vec2::new(40., 40.));
+    let mut positions: Vec<Vec2> = (0..300)
+        .map(|_| Vec2::new(rng.gen_range(0.0..400.0), rng.gen_range(0.0..400.0)))
+        .collect();
+    for (i, p) in positions.iter().enumerate() {
+        map.insert(i as u32, *p);
+    }
+    let mut alive = vec![true; positions.len()];
+    for _ in 0..20 {
+        for (i, p) in positions.iter_mut().enumerate() {
+            if !alive[i] {
+                continue;
+            }
+            let old = *p;
+            *p += Vec2::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0));
+            if rng.gen_bool(0.02) {
+                assert!(map.remove(i as u32));
+                alive[i] = false;
+            } else {
+                map.update_position(i as u32, old, *p);
+            }
+        }
+        let mut rebuilt = Collision::new(Vec2::new(40., 40.));
+        for (i, p) in positions.iter().enumerate() {
+            if alive[i] {
+                rebuilt.insert(i as u32, *p);
+            }
+        }
+        assert_eq!(map.entity_count, rebuilt.entity_count);
+        for _ in 0..10 {
+            let pos = Vec2::new(rng.gen_range(0.0..400.0), rng.gen_range(0.0..400.0));
+            let mut got = map.query_radius(pos, 70.);
+            let mut expected = rebuilt.query_radius(pos, 70.);
+            got.sort();
+            expected.sort();
+            assert_eq!(got, expected);
+        }
+    }
+    assert!(!map.remove(positions.len() as u32 + 5));
+}
+
+#[test]
+fn payloads_travel_with_entities() {
+    // 每个实体把速度存在cell里
+    let mut map =
+        SpaceMap::<CollisionMarker, CellBuildHasher, (u32, Vec2)>::new(Vec2::new(20., 20.));
+    map.with_border_layer(1., 2.);
+    map.insert((0, Vec2::X), Vec2::new(5., 5.));
+    map.insert((1, Vec2::Y), Vec2::new(12., 8.));
+    map.insert((2, Vec2::NEG_X), Vec2::new(55., 5.));
+
+    let mut seen = Vec::new();
+    map.for_each_payload_in_radius(Vec2::new(6., 6.), 10., |payload, offset| {
+        seen.push((*payload, offset))
+    });
+    seen.sort_by_key(|((id, _), _)| *id);
+    assert_eq!(
+        seen,
+        vec![
+            ((0, Vec2::X), Vec2::new(-1., -1.)),
+            ((1, Vec2::Y), Vec2::new(6., 2.))
+        ]
+    );
+
+    // 同一个cell内移动和跨cell移动都会刷新payload
+    map.update_position((0, Vec2::NEG_Y), Vec2::new(5., 5.), Vec2::new(6., 5.));
+    map.update_position((1, Vec2::ONE), Vec2::new(12., 8.), Vec2::new(50., 8.));
+    assert_eq!(
+        map.query(Vec2::new(6., 5.)).unwrap().get_entities(),
+        &[(0, Vec2::NEG_Y)]
+    );
+    let mut ids = map.query_radius(Vec2::new(52., 6.), 5.);
+    ids.sort();
+    assert_eq!(ids, vec![1, 2]);
+    let payloads: Vec<(u32, Vec2)> = map
+        .query(Vec2::new(50., 8.))
+        .unwrap()
+        .get_entities()
+        .to_vec();
+    assert_eq!(payloads, vec![(2, Vec2::NEG_X), (1, Vec2::ONE)]);
+
+    assert!(map.remove(2));
+    assert_eq!(map.nearest(Vec2::new(56., 5.)), Some(1));
+    assert_eq!(map.entity_count(), 2);
+}
+
+#[test]
+fn space_map_3d_matches_brute_force() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(16);
+    let (min, max) = (Vec3::splat(-200.), Vec3::new(200., 160., 240.));
+    let size = max - min;
+    let random = |rng: &mut rand::rngs::StdRng, r: f32| {
+        Vec3::new(
+            rng.gen_range(-r..r),
+            rng.gen_range(-r..r),
+            rng.gen_range(-r..r),
+        )
+    };
+    for wrap in [false, true] {
+        let mut map = SpaceMap3::<CollisionMarker>::new(Vec3::splat(25.));
+        if wrap {
+            map.with_wrap(min, max);
+        }
+        let min_image = |d: Vec3| {
+            if wrap {
+                d - (d / size).round() * size
+            } else {
+                d
+            }
+        };
+        let mut positions: Vec<Vec3> = (0..800).map(|_| random(&mut rng, 200.)).collect();
+        for (i, p) in positions.iter().enumerate() {
+            map.insert(i as u32, *p);
+        }
+        for (i, p) in positions.iter_mut().enumerate() {
+            let old = *p;
+            *p += random(&mut rng, 30.);
+            map.update_position(i as u32, old, *p);
+        }
+        assert!(map.remove(7));
+        assert!(!map.remove(7));
+        let alive = |i: &usize| *i != 7;
+        for _ in 0..30 {
+            let pos = random(&mut rng, 220.);
+            let expected: Vec<u32> = (0..positions.len())
+                .filter(alive)
+                .filter(|i| min_image(positions[*i] - pos).length_squared() <= 60. * 60.)
+                .map(|i| i as u32)
+                .collect();
+            let mut got = map.query_radius(pos, 60.);
+            got.sort();
+            assert_eq!(got, expected);
+
+            if !wrap {
+                let max = pos + Vec3::new(70., 40., 90.);
+                let expected: Vec<u32> = (0..positions.len())
+                    .filter(alive)
+                    .filter(|i| positions[*i].within(pos, max))
+                    .map(|i| i as u32)
+                    .collect();
+                let mut got = map.query_aabb(pos, max);
+                got.sort();
+                assert_eq!(got, expected);
+            }
+
+            let mut expected: Vec<(f32, u32)> = (0..positions.len())
+                .filter(alive)
+                .map(|i| (min_image(positions[i] - pos).length_squared(), i as u32))
+                .collect();
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            let expected: Vec<u32> = expected.into_iter().take(5).map(|(_, i)| i).collect();
+            assert_eq!(map.nearest_k(pos, 5, f32::INFINITY), expected);
+        }
+
+        let mut expected = Vec::new();
+        for a in (0..positions.len()).filter(alive) {
+            for b in ((a + 1)..positions.len()).filter(alive) {
+                if min_image(positions[b] - positions[a]).length() <= 30. {
+                    expected.push((a as u32, b as u32));
+                }
+            }
+        }
+        let mut got: Vec<(u32, u32)> = map
+            .pairs_within(30.)
+            .into_iter()
+            .map(|(a, b, _)| (a, b))
+            .collect();
+        got.sort();
+        assert_eq!(got, expected);
     }
 }
 
@@ -158,14 +670,81 @@ impl Ready for Space {
         data: &mut ready_paint::scene::HashTypeId2Data,
         gfx: &ready_paint::gfx::Gfx,
     ) {
-        return_res(
-            data,
-            Space {
-                maps: Box::new((
-                    Collision::new(Vec2::new(200., 200.)),
-                    Clustering::new(Vec2::new(500., 500.)),
-                )),
-            },
+        let config = get_res::<Config>(data);
+        let boid_config = get_res::<BoidConfig>(data);
+        // 实体会在窗口外 boundary_margin 的范围内绕回
+        let surface_config = gfx.surface_config.as_ref().unwrap();
+        let window_size = Vec2::new(surface_config.width as f32, surface_config.height as f32);
+        let bounds = (
+            Vec2::splat(-boid_config.boundary_margin),
+            window_size + Vec2::splat(boid_config.boundary_margin),
+        );
+        let border = (config.entity_radius, boid_config.separation_radius);
+        let border_line_width = boid_config.separation_radius + 2. * config.entity_radius;
+        // cell大小从两层各自的查询半径算出来
+        let collision_border = config.collision_border_layer.then_some(border_line_width);
+        let clustering_border = config.clustering_border_layer.then_some(border_line_width);
+        let collision_radius = boid_config.collision_radius();
+        let clustering_radius = boid_config.clustering_radius();
+        let mut space = Space::default();
+        add_level::<CollisionMarker, u32>(
+            &mut space,
+            "collision",
+            config.collision_backend,
+            Vec2::splat(cell_size_for(collision_radius, collision_border)),
+            bounds,
+            config.collision_border_layer.then_some(border),
+            config,
         );
+        add_level::<ClusteringMarker, (u32, Vec2)>(
+            &mut space,
+            "clustering",
+            config.clustering_backend,
+            Vec2::splat(cell_size_for(clustering_radius, clustering_border)),
+            bounds,
+            config.clustering_border_layer.then_some(border),
+            config,
+        );
+        if config.auto_tune_cells {
+            space.set_tuning(
+                "collision",
+                CellTuning::for_radius(collision_radius, collision_border),
+            );
+            space.set_tuning(
+                "clustering",
+                CellTuning::for_radius(clustering_radius, clustering_border),
+            );
+        }
+        return_res(data, space);
+    }
+}
+
+/// 按选择的后端给 `Space` 注册一层，边界层、环面世界和cell复用只有 `SpaceMap` 支持
+/// `P` 是 `SpaceMap` 后端在cell里存的payload
+fn add_level<M: 'static, P: IndexPayload<Vec2> + Send + Sync + 'static>(
+    space: &mut Space,
+    name: &'static str,
+    backend: SpatialBackend,
+    cell_size: Vec2,
+    (min, max): (Vec2, Vec2),
+    border: Option<(f32, f32)>,
+    config: &Config,
+) {
+    match backend {
+        SpatialBackend::Hash => {
+            let mut map = SpaceMap::<M, CellBuildHasher, P>::new(cell_size);
+            if config.wrap_space {
+                map.with_wrap(min, max);
+            }
+            if let Some(evict_after) = config.bucket_reuse_frames {
+                map.with_bucket_reuse(evict_after);
+            }
+            if let Some((object_radius, separate_dis)) = border {
+                map.with_border_layer(object_radius, separate_dis);
+            }
+            space.add_level(name, map);
+        }
+        SpatialBackend::Dense => space.add_level(name, DenseGrid::<M>::new(min, max, cell_size)),
+        SpatialBackend::Quadtree => space.add_level(name, LooseQuadtree::<M>::new(min, max)),
     }
 }
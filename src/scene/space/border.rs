@@ -0,0 +1,285 @@
+use std::{collections::HashMap, hash::BuildHasher};
+
+use glam::{IVec2, Vec2};
+
+use super::{dim::SpaceCell, unit::IndexGrid, BorderDir, Payload, SpaceMap, SpaceVector};
+
+impl BorderDir {
+    /// 从所在cell指向这个方向的邻居cell的偏移
+    pub fn offset(self) -> IVec2 {
+        match self {
+            BorderDir::LT => IVec2::new(-1, 1),
+            BorderDir::RT => IVec2::new(1, 1),
+            BorderDir::LB => IVec2::new(-1, -1),
+            BorderDir::RB => IVec2::new(1, -1),
+            BorderDir::L => IVec2::new(-1, 0),
+            BorderDir::R => IVec2::new(1, 0),
+            BorderDir::T => IVec2::new(0, 1),
+            BorderDir::B => IVec2::new(0, -1),
+        }
+    }
+
+    /// 相反方向，邻居cell里靠近这边的实体在它的这个方向上
+    pub fn opposite(self) -> BorderDir {
+        match self {
+            BorderDir::LT => BorderDir::RB,
+            BorderDir::RT => BorderDir::LB,
+            BorderDir::LB => BorderDir::RT,
+            BorderDir::RB => BorderDir::LT,
+            BorderDir::L => BorderDir::R,
+            BorderDir::R => BorderDir::L,
+            BorderDir::T => BorderDir::B,
+            BorderDir::B => BorderDir::T,
+        }
+    }
+}
+
+const ALL_DIRS: [BorderDir; 8] = [
+    BorderDir::LT,
+    BorderDir::RT,
+    BorderDir::LB,
+    BorderDir::RB,
+    BorderDir::L,
+    BorderDir::R,
+    BorderDir::T,
+    BorderDir::B,
+];
+
+impl<T, S: BuildHasher + Clone, P: Payload> SpaceMap<T, S, P> {
+    // add border layer
+    // the obj radius and distance for calculating to 2 * r + dis,
+    // keep a full enough distance to
+    pub fn with_border_layer(&mut self, object_radius: f32, object_center_separate_dis: f32) {
+        self.enable_border_layer(object_center_separate_dis + 2. * object_radius);
+        // 已经在map里的实体补进边界层
+        let entities: Vec<(IVec2, u32, Vec2)> = self
+            .map
+            .iter()
+            .flat_map(|(cell, grid)| {
+                grid.ids()
+                    .zip(grid.positions.iter())
+                    .map(|(id, p)| (*cell, id, *p))
+            })
+            .collect();
+        for (cell, id, p) in entities {
+            self.insert_border(id, cell, p);
+        }
+    }
+
+    /// 查询 `cell` 中靠近 `dir` 这一边（或角）的实体
+    pub fn query_border(&self, cell: IVec2, dir: BorderDir) -> Option<&IndexGrid> {
+        self.border_layer_map.as_ref()?.get(&(cell, dir))
+    }
+
+    /// 位于 `position` 的实体需要查看的cell：自己所在的cell，加上它靠近的边和角对应的邻居
+    /// 没有开启边界层时保守地返回周围全部9个cell，环面世界里邻居cell会绕回来
+    pub fn neighbor_cells(&self, position: Vec2) -> impl Iterator<Item = IVec2> {
+        self.border_neighbor_cells(position)
+    }
+}
+
+/// 边界层只在2D里有，cell和位置通过 `planar` 换成2D的，其它维度什么都不做
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 开启空的边界层，已有的实体由调用方补进来
+    pub(super) fn enable_border_layer(&mut self, border_line_width: f32) {
+        self.border_layer_map = Some(HashMap::with_hasher(self.map.hasher().clone()));
+        self.border_line_width = border_line_width;
+        self.update_border_entry();
+    }
+
+    /// 和cell中心的距离超过 entry 就算进入了边界带，cell大小变了要重新算
+    pub(super) fn update_border_entry(&mut self) {
+        if self.border_layer_map.is_none() {
+            return;
+        }
+        if let Some(cell_size) = self.cell_size.planar() {
+            self.x_entry = (cell_size.x / 2. - self.border_line_width).max(0.);
+            self.y_entry = (cell_size.y / 2. - self.border_line_width).max(0.);
+        }
+    }
+
+    pub fn has_border_layer(&self) -> bool {
+        self.border_layer_map.is_some()
+    }
+
+    /// 半径 `r` 的范围查询能否只用边界层给出的邻居cell
+    /// 要求 `r` 不超过边界带宽度，并且边界带没有超过半个cell
+    pub(super) fn border_covers(&self, r: f32) -> bool {
+        self.border_layer_map.is_some()
+            && r <= self.border_line_width
+            && self.border_line_width * 2. <= self.cell_size.min_element()
+            && self.wrap_has_distinct_neighbors()
+    }
+
+    /// 实体靠近cell的哪些边和角，最多一条竖边、一条横边和它们夹着的角
+    pub(super) fn border_dirs(
+        &self,
+        cell_pos: V::Cell,
+        position: V,
+    ) -> impl Iterator<Item = BorderDir> {
+        let dis = (position - self.get_cell_center(&cell_pos))
+            .planar()
+            .unwrap_or(Vec2::ZERO);
+        let horizontal = if dis.x > self.x_entry {
+            Some(BorderDir::R)
+        } else if dis.x < -self.x_entry {
+            Some(BorderDir::L)
+        } else {
+            None
+        };
+        let vertical = if dis.y > self.y_entry {
+            Some(BorderDir::T)
+        } else if dis.y < -self.y_entry {
+            Some(BorderDir::B)
+        } else {
+            None
+        };
+        let corner = match (horizontal, vertical) {
+            (Some(BorderDir::R), Some(BorderDir::T)) => Some(BorderDir::RT),
+            (Some(BorderDir::L), Some(BorderDir::T)) => Some(BorderDir::LT),
+            (Some(BorderDir::R), Some(BorderDir::B)) => Some(BorderDir::RB),
+            (Some(BorderDir::L), Some(BorderDir::B)) => Some(BorderDir::LB),
+            _ => None,
+        };
+        [horizontal, vertical, corner].into_iter().flatten()
+    }
+
+    pub(super) fn insert_border(&mut self, entity_id: u32, cell_pos: V::Cell, position: V) {
+        let (Some(cell), Some(planar)) = (cell_pos.planar(), position.planar()) else {
+            return;
+        };
+        if self.border_layer_map.is_none() {
+            return;
+        }
+        let dirs = self.border_dirs(cell_pos, position);
+        let border_map = self.border_layer_map.as_mut().unwrap();
+        for border_dir in dirs {
+            border_map
+                .entry((cell, border_dir))
+                .or_insert(IndexGrid::new())
+                .insert(entity_id, planar);
+        }
+    }
+
+    pub(super) fn remove_border(&mut self, entity_id: u32, cell_pos: V::Cell, position: V) {
+        let Some(cell) = cell_pos.planar() else {
+            return;
+        };
+        if self.border_layer_map.is_none() {
+            return;
+        }
+        let dirs = self.border_dirs(cell_pos, position);
+        let border_map = self.border_layer_map.as_mut().unwrap();
+        for border_dir in dirs {
+            if let Some(grid) = border_map.get_mut(&(cell, border_dir)) {
+                if let Some(index) = grid.payloads.iter().position(|id| *id == entity_id) {
+                    grid.swap_remove(index);
+                }
+            }
+        }
+    }
+
+    /// `neighbor_cells` 的实现，给出map里的key
+    pub(super) fn border_neighbor_cells(&self, position: V) -> impl Iterator<Item = V::Cell> {
+        let cell_pos = self.get_cell_index(position);
+        let wrap = self.wrap;
+        let (border_dirs, all_dirs) = if self.border_layer_map.is_some() {
+            (Some(self.border_dirs(cell_pos, position)), None)
+        } else {
+            (None, Some(ALL_DIRS))
+        };
+        std::iter::once(cell_pos).chain(
+            border_dirs
+                .into_iter()
+                .flatten()
+                .chain(all_dirs.into_iter().flatten())
+                .filter_map(move |dir| {
+                    let neighbor = V::Cell::from_planar(cell_pos.planar()? + dir.offset())?;
+                    Some(wrap.map_or(neighbor, |wrap| wrap.wrap_cell(neighbor)))
+                }),
+        )
+    }
+}
+
+#[test]
+fn border_layer_tracks_edges_and_corners() {
+    let mut map = super::Collision::new(Vec2::new(100., 100.));
+    // 宽度 10 + 2 * 5 = 20
+    map.with_border_layer(5., 10.);
+    map.insert(0, Vec2::new(50., 50.));
+    map.insert(1, Vec2::new(95., 50.));
+    map.insert(2, Vec2::new(95., 95.));
+    map.insert(3, Vec2::new(50., 3.));
+    let cell = IVec2::ZERO;
+    let ids = |map: &super::Collision, dir| {
+        map.query_border(cell, dir)
+            .map(|g| g.get_entities().to_vec())
+            .unwrap_or_default()
+    };
+    assert_eq!(ids(&map, BorderDir::R), vec![1, 2]);
+    assert_eq!(ids(&map, BorderDir::T), vec![2]);
+    assert_eq!(ids(&map, BorderDir::RT), vec![2]);
+    assert_eq!(ids(&map, BorderDir::B), vec![3]);
+    assert_eq!(ids(&map, BorderDir::L), Vec::<u32>::new());
+
+    let cells = |map: &super::Collision, p| map.neighbor_cells(p).collect::<Vec<_>>();
+    assert_eq!(cells(&map, Vec2::new(50., 50.)), vec![cell]);
+    assert_eq!(
+        cells(&map, Vec2::new(95., 95.)),
+        vec![cell, IVec2::new(1, 0), IVec2::new(0, 1), IVec2::new(1, 1)]
+    );
+
+    map.update_position(2, Vec2::new(95., 95.), Vec2::new(50., 95.));
+    assert_eq!(ids(&map, BorderDir::R), vec![1]);
+    assert_eq!(ids(&map, BorderDir::RT), Vec::<u32>::new());
+    assert_eq!(ids(&map, BorderDir::T), vec![2]);
+
+    map.clear();
+    assert!(map.query_border(cell, BorderDir::R).is_none());
+}
+
+#[test]
+fn query_radius_with_border_layer_matches_full_scan() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(8);
+    let mut with_border = super::Collision::new(Vec2::new(60., 60.));
+    with_border.with_border_layer(5., 10.);
+    let mut plain = super::Collision::new(Vec2::new(60., 60.));
+    for i in 0..500 {
+        let p = Vec2::new(rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0));
+        with_border.insert(i, p);
+        plain.insert(i, p);
+    }
+    for _ in 0..200 {
+        let pos = Vec2::new(rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0));
+        for r in [5., 20., 45.] {
+            let mut got = with_border.query_radius(pos, r);
+            let mut expected = plain.query_radius(pos, r);
+            got.sort();
+            expected.sort();
+            assert_eq!(got, expected);
+        }
+    }
+}
+
+#[test]
+fn neighbor_cells_cover_separation_radius() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let mut map = super::Collision::new(Vec2::new(60., 60.));
+    map.with_border_layer(5., 10.);
+    let points: Vec<Vec2> = (0..400)
+        .map(|_| Vec2::new(rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0)))
+        .collect();
+    for (i, p) in points.iter().enumerate() {
+        map.insert(i as u32, *p);
+    }
+    for (i, p) in points.iter().enumerate() {
+        let cells: Vec<IVec2> = map.neighbor_cells(*p).collect();
+        for (j, q) in points.iter().enumerate() {
+            if i != j && p.distance(*q) <= 20. {
+                assert!(cells.contains(&map.get_cell_index(*q)));
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::hash::BuildHasher;
+
+use super::{dim::SpaceCell, Payload, SpaceMap, SpaceVector};
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 遍历有点实体的cell，给出 `(cell坐标, payload)`，默认的payload就是实体id，顺序不固定
+    /// 已经空了但还留在map里的cell会被跳过，有大小的实体不在这里
+    pub fn cells(&self) -> impl Iterator<Item = (V::Cell, &[P])> + '_ {
+        self.map
+            .iter()
+            .filter(|(_, grid)| !grid.payloads.is_empty())
+            .map(|(cell_pos, grid)| (*cell_pos, grid.get_entities()))
+    }
+
+    /// `cells` 覆盖的cell坐标范围 `(min, max)`，两端都包含，没有点实体时返回 `None`
+    pub fn occupied_bounds(&self) -> Option<(V::Cell, V::Cell)> {
+        let mut cells = self.cells().map(|(cell_pos, _)| cell_pos);
+        let first = cells.next()?;
+        Some(cells.fold((first, first), |(min, max), cell_pos| {
+            (min.min(cell_pos), max.max(cell_pos))
+        }))
+    }
+
+    /// map里的实体数，点实体和有大小的实体一起算
+    pub fn entity_count(&self) -> usize {
+        self.entity_count
+    }
+}
+
+#[test]
+fn cells_and_bounds_skip_empty_cells() {
+    use glam::{IVec2, Vec2};
+    let mut map = super::Collision::new(Vec2::new(10., 10.));
+    assert_eq!(map.occupied_bounds(), None);
+    map.insert(0, Vec2::new(5., 5.));
+    map.insert(1, Vec2::new(7., 2.));
+    map.insert(2, Vec2::new(-15., 31.));
+    map.insert(3, Vec2::new(95., -40.));
+    map.insert_circle(4, Vec2::new(200., 200.), 5.);
+    map.remove(3);
+
+    let mut cells: Vec<(IVec2, Vec<u32>)> = map
+        .cells()
+        .map(|(cell_pos, ids)| (cell_pos, ids.to_vec()))
+        .collect();
+    cells.sort_by_key(|(cell_pos, _)| (cell_pos.y, cell_pos.x));
+    assert_eq!(
+        cells,
+        vec![(IVec2::new(0, 0), vec![0, 1]), (IVec2::new(-2, 3), vec![2])]
+    );
+    assert_eq!(
+        map.occupied_bounds(),
+        Some((IVec2::new(-2, 0), IVec2::new(0, 3)))
+    );
+    assert_eq!(map.entity_count(), 4);
+
+    // 访问者和 `query_radius_offsets` 看到同样的实体
+    let mut visited = Vec::new();
+    map.for_each_in_radius(Vec2::new(0., 10.), 40., |id, offset| {
+        visited.push((id, offset))
+    });
+    assert_eq!(visited, map.query_radius_offsets(Vec2::new(0., 10.), 40.));
+    assert_eq!(visited.len(), 3);
+}
@@ -0,0 +1,325 @@
+use std::marker::PhantomData;
+
+use glam::{IVec2, Vec2};
+
+/// 已知世界范围时使用的稠密网格
+/// 所有cell放在一个扁平数组里，每帧用计数排序按cell重排实体，
+/// 重建时只复用已有的几个 `Vec`，热身之后不会再分配内存
+pub struct DenseGrid<T> {
+    origin: Vec2,
+    cell_size: Vec2,
+    dims: IVec2,
+    // 每个cell在 entity_ids / positions 中的起始下标和实体数量
+    cell_start: Vec<u32>,
+    cell_count: Vec<u32>,
+    // 按cell排好序的实体
+    entity_ids: Vec<u32>,
+    positions: Vec<Vec2>,
+    // insert 进来还没 build 的实体
+    staged: Vec<(u32, Vec2, u32)>,
+    cursor: Vec<u32>,
+    // 只用来区分层，不影响 Send/Sync
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// 稠密网格中一个cell的内容，和 `IndexGrid` 的读取方式一致
+#[derive(Clone, Copy)]
+pub struct DenseCell<'a> {
+    pub entity_ids: &'a [u32],
+    pub positions: &'a [Vec2],
+}
+
+impl DenseCell<'_> {
+    pub fn get_entities(&self) -> &[u32] {
+        self.entity_ids
+    }
+    pub fn get_positions(&self) -> &[Vec2] {
+        self.positions
+    }
+}
+
+impl<T> DenseGrid<T> {
+    /// 覆盖 `[min, max]` 的网格，范围外的实体会被夹到最边上的cell里
+    pub fn new(min: Vec2, max: Vec2, cell_size: Vec2) -> Self {
+        let dims = ((max - min) / cell_size).ceil().as_ivec2().max(IVec2::ONE);
+        let cells = (dims.x * dims.y) as usize;
+        Self {
+            origin: min,
+            cell_size,
+            dims,
+            cell_start: vec![0; cells],
+            cell_count: vec![0; cells],
+            entity_ids: Vec::new(),
+            positions: Vec::new(),
+            staged: Vec::new(),
+            cursor: vec![0; cells],
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn cell_size(&self) -> Vec2 {
+        self.cell_size
+    }
+
+    pub fn origin(&self) -> Vec2 {
+        self.origin
+    }
+
+    /// 清空已插入的实体，保留所有缓冲区的容量
+    pub fn clear(&mut self) {
+        self.staged.clear();
+        self.entity_ids.clear();
+        self.positions.clear();
+        self.cell_count.fill(0);
+        self.cell_start.fill(0);
+    }
+
+    /// 暂存实体，调用 `build` 之后才能被查询到
+    pub fn insert(&mut self, entity_id: u32, position: Vec2) {
+        let cell = self.get_cell_index(position);
+        let index = self.flat_index(cell);
+        self.staged.push((entity_id, position, index));
+    }
+
+    /// 从暂存中移除实体，同样要 `build` 之后才生效
+    pub fn remove(&mut self, entity_id: u32) -> bool {
+        let Some(index) = self.staged.iter().position(|(id, _, _)| *id == entity_id) else {
+            return false;
+        };
+        self.staged.swap_remove(index);
+        true
+    }
+
+    /// 计数排序：先数每个cell的实体数，前缀和得到起始下标，再把实体放到对应位置
+    pub fn build(&mut self) {
+        self.cell_count.fill(0);
+        for (_, _, index) in self.staged.iter() {
+            self.cell_count[*index as usize] += 1;
+        }
+        let mut start = 0;
+        for (cell_start, count) in self.cell_start.iter_mut().zip(self.cell_count.iter()) {
+            *cell_start = start;
+            start += count;
+        }
+        self.cursor.copy_from_slice(&self.cell_start);
+        self.entity_ids.resize(self.staged.len(), 0);
+        self.positions.resize(self.staged.len(), Vec2::ZERO);
+        for (id, position, index) in self.staged.iter() {
+            let slot = &mut self.cursor[*index as usize];
+            self.entity_ids[*slot as usize] = *id;
+            self.positions[*slot as usize] = *position;
+            *slot += 1;
+        }
+    }
+
+    /// 一次性用新的实体集合重建
+    pub fn rebuild(&mut self, entities: impl IntoIterator<Item = (u32, Vec2)>) {
+        self.staged.clear();
+        for (id, position) in entities {
+            self.insert(id, position);
+        }
+        self.build();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entity_ids.len()
+    }
+
+    /// 查询某个位置的cell
+    pub fn query(&self, entity_pos: Vec2) -> Option<DenseCell<'_>> {
+        let cell = self.get_cell_index(entity_pos);
+        let cell = self.cell(self.flat_index(cell));
+        (!cell.entity_ids.is_empty()).then_some(cell)
+    }
+
+    /// 查询圆形范围内的实体，和 `SpaceMap::query_radius` 一致
+    pub fn query_radius(&self, pos: Vec2, r: f32) -> Vec<u32> {
+        let mut result = Vec::new();
+        let r2 = r * r;
+        let min_cell = self.get_cell_index(pos - Vec2::splat(r));
+        let max_cell = self.get_cell_index(pos + Vec2::splat(r));
+        for y in min_cell.y..=max_cell.y {
+            for x in min_cell.x..=max_cell.x {
+                let cell = self.cell(self.flat_index(IVec2::new(x, y)));
+                for (id, p) in cell.entity_ids.iter().zip(cell.positions.iter()) {
+                    if p.distance_squared(pos) <= r2 {
+                        result.push(*id);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// 查询轴对齐矩形 `[min, max]`（含边界）内的实体
+    pub fn query_aabb(&self, min: Vec2, max: Vec2) -> Vec<u32> {
+        let mut result = Vec::new();
+        let min_cell = self.get_cell_index(min);
+        let max_cell = self.get_cell_index(max);
+        for y in min_cell.y..=max_cell.y {
+            for x in min_cell.x..=max_cell.x {
+                let cell = self.cell(self.flat_index(IVec2::new(x, y)));
+                for (id, p) in cell.entity_ids.iter().zip(cell.positions.iter()) {
+                    if p.cmpge(min).all() && p.cmple(max).all() {
+                        result.push(*id);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// 距离 `pos` 最近的实体，距离相同时取id小的
+    pub fn nearest(&self, pos: Vec2) -> Option<u32> {
+        let center = self.get_cell_index(pos);
+        let mut best: Option<(f32, u32)> = None;
+        let max_ring = self.dims.max_element();
+        for ring in 0..=max_ring {
+            self.for_each_ring_cell(center, ring, |cell| {
+                for (id, p) in cell.entity_ids.iter().zip(cell.positions.iter()) {
+                    let candidate = (p.distance_squared(pos), *id);
+                    let closer = match best {
+                        Some(b) => candidate.0 < b.0 || (candidate.0 == b.0 && candidate.1 < b.1),
+                        None => true,
+                    };
+                    if closer {
+                        best = Some(candidate);
+                    }
+                }
+            });
+            // 还没访问的cell离 pos 至少这么远，网格边界之外没有cell
+            let side = |lo: i32, hi: i32, axis: usize| {
+                let low = if lo > 0 {
+                    pos[axis] - (self.origin[axis] + lo as f32 * self.cell_size[axis])
+                } else {
+                    f32::INFINITY
+                };
+                let high = if hi < self.dims[axis] {
+                    self.origin[axis] + hi as f32 * self.cell_size[axis] - pos[axis]
+                } else {
+                    f32::INFINITY
+                };
+                low.min(high)
+            };
+            let outside = side(center.x - ring, center.x + ring + 1, 0).min(side(
+                center.y - ring,
+                center.y + ring + 1,
+                1,
+            ));
+            if best.is_some_and(|b| b.0 <= outside * outside) {
+                break;
+            }
+        }
+        best.map(|b| b.1)
+    }
+
+    /// 访问和 `center` 切比雪夫距离正好为 `ring` 并且在网格内的cell
+    fn for_each_ring_cell(&self, center: IVec2, ring: i32, mut f: impl FnMut(DenseCell<'_>)) {
+        let mut visit = |cell: IVec2| {
+            if cell.x >= 0 && cell.y >= 0 && cell.x < self.dims.x && cell.y < self.dims.y {
+                f(self.cell(self.flat_index(cell)));
+            }
+        };
+        if ring == 0 {
+            visit(center);
+            return;
+        }
+        for x in -ring..=ring {
+            visit(center + IVec2::new(x, -ring));
+            visit(center + IVec2::new(x, ring));
+        }
+        for y in (-ring + 1)..ring {
+            visit(center + IVec2::new(-ring, y));
+            visit(center + IVec2::new(ring, y));
+        }
+    }
+
+    /// 根据位置返回cell的索引，范围外的夹到边上
+    fn get_cell_index(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, self.dims - IVec2::ONE)
+    }
+
+    fn flat_index(&self, cell: IVec2) -> u32 {
+        (cell.y * self.dims.x + cell.x) as u32
+    }
+
+    fn cell(&self, index: u32) -> DenseCell<'_> {
+        let start = self.cell_start[index as usize] as usize;
+        let end = start + self.cell_count[index as usize] as usize;
+        DenseCell {
+            entity_ids: &self.entity_ids[start..end],
+            positions: &self.positions[start..end],
+        }
+    }
+}
+
+#[test]
+fn dense_grid_matches_space_map() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(9);
+    let mut dense = DenseGrid::<super::CollisionMarker>::new(
+        Vec2::ZERO,
+        Vec2::new(800., 600.),
+        Vec2::new(50., 50.),
+    );
+    for _ in 0..3 {
+        // 留一些实体在范围外
+        let points: Vec<Vec2> = (0..1000)
+            .map(|_| Vec2::new(rng.gen_range(-50.0..850.0), rng.gen_range(-50.0..650.0)))
+            .collect();
+        let mut map = super::Collision::new(Vec2::new(50., 50.));
+        for (i, p) in points.iter().enumerate() {
+            map.insert(i as u32, *p);
+        }
+        dense.rebuild(points.iter().enumerate().map(|(i, p)| (i as u32, *p)));
+        assert_eq!(dense.len(), points.len());
+        for _ in 0..30 {
+            let pos = Vec2::new(rng.gen_range(-100.0..900.0), rng.gen_range(-100.0..700.0));
+            let r = rng.gen_range(1.0..200.0);
+            let mut got = dense.query_radius(pos, r);
+            let mut expected = map.query_radius(pos, r);
+            got.sort();
+            expected.sort();
+            assert_eq!(got, expected);
+
+            let max = pos + Vec2::new(rng.gen_range(0.0..300.0), rng.gen_range(0.0..300.0));
+            let mut got = dense.query_aabb(pos, max);
+            let mut expected = map.query_aabb(pos, max);
+            got.sort();
+            expected.sort();
+            assert_eq!(got, expected);
+        }
+        let inside = Vec2::new(420., 310.);
+        let mut got = dense
+            .query(inside)
+            .map(|c| c.get_entities().to_vec())
+            .unwrap_or_default();
+        let mut expected = map
+            .query(inside)
+            .map(|g| g.get_entities().to_vec())
+            .unwrap_or_default();
+        got.sort();
+        expected.sort();
+        assert_eq!(got, expected);
+    }
+}
+
+#[test]
+fn dense_grid_rebuild_reuses_buffers() {
+    let mut dense =
+        DenseGrid::<super::CollisionMarker>::new(Vec2::ZERO, Vec2::splat(100.), Vec2::splat(10.));
+    let points: Vec<(u32, Vec2)> = (0..500)
+        .map(|i| (i, Vec2::new((i % 97) as f32, (i % 89) as f32)))
+        .collect();
+    dense.rebuild(points.iter().copied());
+    let ids_ptr = dense.entity_ids.as_ptr();
+    let staged_ptr = dense.staged.as_ptr();
+    for _ in 0..10 {
+        dense.rebuild(points.iter().rev().copied());
+    }
+    assert_eq!(dense.entity_ids.as_ptr(), ids_ptr);
+    assert_eq!(dense.staged.as_ptr(), staged_ptr);
+}
@@ -0,0 +1,395 @@
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, Sub, SubAssign},
+};
+
+use glam::{IVec2, IVec3, Vec2, Vec3};
+
+/// cell的整数坐标，2D是 `IVec2`，3D是 `IVec3`
+pub trait SpaceCell:
+    Copy + Eq + Hash + Debug + Send + Sync + 'static + Add<Output = Self> + Sub<Output = Self>
+{
+    /// 维度数，2D是2，3D是3
+    const DIM: u32;
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn min(self, rhs: Self) -> Self;
+    fn max(self, rhs: Self) -> Self;
+    fn abs(self) -> Self;
+    fn max_element(self) -> i32;
+    fn min_element(self) -> i32;
+    fn rem_euclid(self, rhs: Self) -> Self;
+    /// 排序用的key，高维的坐标在前，2D时是 `(y, x)`
+    fn sort_key(self) -> [i32; 3];
+    /// 边界层只有2D有，其它维度返回 `None`
+    fn planar(self) -> Option<IVec2> {
+        None
+    }
+    fn from_planar(_cell: IVec2) -> Option<Self> {
+        None
+    }
+}
+
+impl SpaceCell for IVec2 {
+    const DIM: u32 = 2;
+    const ZERO: Self = IVec2::ZERO;
+    const ONE: Self = IVec2::ONE;
+
+    fn min(self, rhs: Self) -> Self {
+        IVec2::min(self, rhs)
+    }
+    fn max(self, rhs: Self) -> Self {
+        IVec2::max(self, rhs)
+    }
+    fn abs(self) -> Self {
+        IVec2::abs(self)
+    }
+    fn max_element(self) -> i32 {
+        IVec2::max_element(self)
+    }
+    fn min_element(self) -> i32 {
+        IVec2::min_element(self)
+    }
+    fn rem_euclid(self, rhs: Self) -> Self {
+        IVec2::rem_euclid(self, rhs)
+    }
+    fn sort_key(self) -> [i32; 3] {
+        [0, self.y, self.x]
+    }
+    fn planar(self) -> Option<IVec2> {
+        Some(self)
+    }
+    fn from_planar(cell: IVec2) -> Option<Self> {
+        Some(cell)
+    }
+}
+
+impl SpaceCell for IVec3 {
+    const DIM: u32 = 3;
+    const ZERO: Self = IVec3::ZERO;
+    const ONE: Self = IVec3::ONE;
+
+    fn min(self, rhs: Self) -> Self {
+        IVec3::min(self, rhs)
+    }
+    fn max(self, rhs: Self) -> Self {
+        IVec3::max(self, rhs)
+    }
+    fn abs(self) -> Self {
+        IVec3::abs(self)
+    }
+    fn max_element(self) -> i32 {
+        IVec3::max_element(self)
+    }
+    fn min_element(self) -> i32 {
+        IVec3::min_element(self)
+    }
+    fn rem_euclid(self, rhs: Self) -> Self {
+        IVec3::rem_euclid(self, rhs)
+    }
+    fn sort_key(self) -> [i32; 3] {
+        [self.z, self.y, self.x]
+    }
+}
+
+/// 空间索引和boid规则用到的向量操作，2D用 `Vec2`/`IVec2`，3D用 `Vec3`/`IVec3`
+pub trait SpaceVector:
+    Copy
+    + PartialEq
+    + Debug
+    + Default
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<f32, Output = Self>
+    + Div<f32, Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + AddAssign
+    + SubAssign
+    + DivAssign<f32>
+{
+    /// cell的整数坐标
+    type Cell: SpaceCell;
+
+    const ZERO: Self;
+
+    fn splat(v: f32) -> Self;
+    fn dot(self, rhs: Self) -> f32;
+    fn length(self) -> f32;
+    fn length_squared(self) -> f32;
+    fn distance_squared(self, rhs: Self) -> f32;
+    fn normalize(self) -> Self;
+    fn abs(self) -> Self;
+    fn min(self, rhs: Self) -> Self;
+    fn max(self, rhs: Self) -> Self;
+    fn min_element(self) -> f32;
+    fn max_element(self) -> f32;
+    fn clamp(self, min: Self, max: Self) -> Self;
+    fn round(self) -> Self;
+    fn ceil(self) -> Self;
+    fn rem_euclid(self, rhs: Self) -> Self;
+    /// 每个分量都满足 `min <= self <= max`
+    fn within(self, min: Self, max: Self) -> bool;
+    /// 每个分量都满足 `self <= rhs`
+    fn le_all(self, rhs: Self) -> bool;
+    /// 按分量转换成整数坐标（向零取整）
+    fn as_cell(self) -> Self::Cell;
+    fn from_cell(cell: Self::Cell) -> Self;
+    /// 所有分量，打印用
+    fn components(self) -> Vec<f32>;
+    /// 边界层只有2D有，其它维度返回 `None`
+    fn planar(self) -> Option<Vec2> {
+        None
+    }
+    /// 所在cell的坐标，`floor(self / cell_size)`
+    fn cell(self, cell_size: Self) -> Self::Cell;
+    /// cell的最小角
+    fn cell_min(cell: Self::Cell, cell_size: Self) -> Self;
+    /// 访问cell盒子 `[min, max]` 中的所有cell
+    fn for_each_cell(min: Self::Cell, max: Self::Cell, f: impl FnMut(Self::Cell));
+    /// 访问和 `center` 切比雪夫距离正好为 `ring` 的所有cell
+    fn for_each_ring_cell(center: Self::Cell, ring: i32, f: impl FnMut(Self::Cell));
+    /// `center` 周围 `ring` 圈以内cell盒子的两个角
+    fn ring_bounds(center: Self::Cell, ring: i32, cell_size: Self) -> (Self, Self);
+}
+
+impl SpaceVector for Vec2 {
+    type Cell = IVec2;
+
+    const ZERO: Self = Vec2::ZERO;
+
+    fn splat(v: f32) -> Self {
+        Vec2::splat(v)
+    }
+    fn dot(self, rhs: Self) -> f32 {
+        Vec2::dot(self, rhs)
+    }
+    fn length(self) -> f32 {
+        Vec2::length(self)
+    }
+    fn length_squared(self) -> f32 {
+        Vec2::length_squared(self)
+    }
+    fn distance_squared(self, rhs: Self) -> f32 {
+        Vec2::distance_squared(self, rhs)
+    }
+    fn normalize(self) -> Self {
+        Vec2::normalize(self)
+    }
+    fn abs(self) -> Self {
+        Vec2::abs(self)
+    }
+    fn min(self, rhs: Self) -> Self {
+        Vec2::min(self, rhs)
+    }
+    fn max(self, rhs: Self) -> Self {
+        Vec2::max(self, rhs)
+    }
+    fn min_element(self) -> f32 {
+        Vec2::min_element(self)
+    }
+    fn max_element(self) -> f32 {
+        Vec2::max_element(self)
+    }
+    fn clamp(self, min: Self, max: Self) -> Self {
+        Vec2::clamp(self, min, max)
+    }
+    fn round(self) -> Self {
+        Vec2::round(self)
+    }
+    fn ceil(self) -> Self {
+        Vec2::ceil(self)
+    }
+    fn rem_euclid(self, rhs: Self) -> Self {
+        Vec2::rem_euclid(self, rhs)
+    }
+    fn within(self, min: Self, max: Self) -> bool {
+        self.cmpge(min).all() && self.cmple(max).all()
+    }
+    fn le_all(self, rhs: Self) -> bool {
+        self.cmple(rhs).all()
+    }
+    fn as_cell(self) -> IVec2 {
+        self.as_ivec2()
+    }
+    fn from_cell(cell: IVec2) -> Self {
+        cell.as_vec2()
+    }
+    fn components(self) -> Vec<f32> {
+        self.to_array().to_vec()
+    }
+    fn cell(self, cell_size: Self) -> IVec2 {
+        IVec2::new(
+            (self.x / cell_size.x).floor() as i32,
+            (self.y / cell_size.y).floor() as i32,
+        )
+    }
+    fn cell_min(cell: IVec2, cell_size: Self) -> Self {
+        cell.as_vec2() * cell_size
+    }
+    fn planar(self) -> Option<Vec2> {
+        Some(self)
+    }
+    fn for_each_cell(min: IVec2, max: IVec2, mut f: impl FnMut(IVec2)) {
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                f(IVec2::new(x, y));
+            }
+        }
+    }
+    fn for_each_ring_cell(center: IVec2, ring: i32, mut f: impl FnMut(IVec2)) {
+        if ring == 0 {
+            f(center);
+            return;
+        }
+        for x in -ring..=ring {
+            f(center + IVec2::new(x, -ring));
+            f(center + IVec2::new(x, ring));
+        }
+        for y in (-ring + 1)..ring {
+            f(center + IVec2::new(-ring, y));
+            f(center + IVec2::new(ring, y));
+        }
+    }
+    fn ring_bounds(center: IVec2, ring: i32, cell_size: Self) -> (Self, Self) {
+        (
+            (center - IVec2::splat(ring)).as_vec2() * cell_size,
+            (center + IVec2::splat(ring + 1)).as_vec2() * cell_size,
+        )
+    }
+}
+
+impl SpaceVector for Vec3 {
+    type Cell = IVec3;
+
+    const ZERO: Self = Vec3::ZERO;
+
+    fn splat(v: f32) -> Self {
+        Vec3::splat(v)
+    }
+    fn dot(self, rhs: Self) -> f32 {
+        Vec3::dot(self, rhs)
+    }
+    fn length(self) -> f32 {
+        Vec3::length(self)
+    }
+    fn length_squared(self) -> f32 {
+        Vec3::length_squared(self)
+    }
+    fn distance_squared(self, rhs: Self) -> f32 {
+        Vec3::distance_squared(self, rhs)
+    }
+    fn normalize(self) -> Self {
+        Vec3::normalize(self)
+    }
+    fn abs(self) -> Self {
+        Vec3::abs(self)
+    }
+    fn min(self, rhs: Self) -> Self {
+        Vec3::min(self, rhs)
+    }
+    fn max(self, rhs: Self) -> Self {
+        Vec3::max(self, rhs)
+    }
+    fn min_element(self) -> f32 {
+        Vec3::min_element(self)
+    }
+    fn max_element(self) -> f32 {
+        Vec3::max_element(self)
+    }
+    fn clamp(self, min: Self, max: Self) -> Self {
+        Vec3::clamp(self, min, max)
+    }
+    fn round(self) -> Self {
+        Vec3::round(self)
+    }
+    fn ceil(self) -> Self {
+        Vec3::ceil(self)
+    }
+    fn rem_euclid(self, rhs: Self) -> Self {
+        Vec3::rem_euclid(self, rhs)
+    }
+    fn within(self, min: Self, max: Self) -> bool {
+        self.cmpge(min).all() && self.cmple(max).all()
+    }
+    fn le_all(self, rhs: Self) -> bool {
+        self.cmple(rhs).all()
+    }
+    fn as_cell(self) -> IVec3 {
+        self.as_ivec3()
+    }
+    fn from_cell(cell: IVec3) -> Self {
+        cell.as_vec3()
+    }
+    fn components(self) -> Vec<f32> {
+        self.to_array().to_vec()
+    }
+    fn cell(self, cell_size: Self) -> IVec3 {
+        IVec3::new(
+            (self.x / cell_size.x).floor() as i32,
+            (self.y / cell_size.y).floor() as i32,
+            (self.z / cell_size.z).floor() as i32,
+        )
+    }
+    fn cell_min(cell: IVec3, cell_size: Self) -> Self {
+        cell.as_vec3() * cell_size
+    }
+    fn for_each_cell(min: IVec3, max: IVec3, mut f: impl FnMut(IVec3)) {
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    f(IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+    fn for_each_ring_cell(center: IVec3, ring: i32, mut f: impl FnMut(IVec3)) {
+        if ring == 0 {
+            f(center);
+            return;
+        }
+        // 上下两个整面，中间每层只取一圈
+        for z in -ring..=ring {
+            if z == -ring || z == ring {
+                Self::for_each_cell(
+                    center + IVec3::new(-ring, -ring, z),
+                    center + IVec3::new(ring, ring, z),
+                    &mut f,
+                );
+                continue;
+            }
+            Vec2::for_each_ring_cell(center.truncate(), ring, |c| {
+                f(c.extend(center.z + z));
+            });
+        }
+    }
+    fn ring_bounds(center: IVec3, ring: i32, cell_size: Self) -> (Self, Self) {
+        (
+            (center - IVec3::splat(ring)).as_vec3() * cell_size,
+            (center + IVec3::splat(ring + 1)).as_vec3() * cell_size,
+        )
+    }
+}
+
+#[test]
+fn ring_cells_cover_each_shell_once() {
+    use std::collections::HashSet;
+    let center = IVec3::new(3, -2, 7);
+    let mut seen = HashSet::new();
+    for ring in 0..4 {
+        let mut count = 0;
+        Vec3::for_each_ring_cell(center, ring, |c| {
+            assert_eq!((c - center).abs().max_element(), ring);
+            assert!(seen.insert(c));
+            count += 1;
+        });
+        let side = 2 * ring + 1;
+        let inner = (2 * ring - 1).max(0);
+        assert_eq!(count, side * side * side - inner * inner * inner);
+    }
+}
@@ -1,6 +1,13 @@
 use glam::Vec2;
 use ready_paint::scene::{get_res, get_res_mut, return_res, Pass, Ready, Update};
 
+const LEVEL_COLORS: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.3],
+    [0.0, 0.0, 1.0, 0.3],
+    [0.0, 1.0, 0.0, 0.3],
+    [1.0, 1.0, 0.0, 0.3],
+];
+
 #[derive(Default)]
 pub struct SpaceDraw {
     pub vertex_buffer: Option<wgpu::Buffer>,
@@ -24,46 +31,49 @@ impl Ready for SpaceDraw {
 
         let spaces = get_res::<super::Space>(data);
 
-        let collision_cell = spaces.maps.0.cell_size;
-        let clustering_cell = spaces.maps.1.cell_size;
-
         let mut vertices = Vec::new();
 
-        let add_grid_lines = |vertices: &mut Vec<_Vertex>, cell_size: Vec2, color: [f32; 4]| {
-            // 垂直线
-            let num_vertical = (width as f32 / cell_size.x).ceil() as i32;
-            for i in 0..=num_vertical {
-                let x = i as f32 * cell_size.x;
-                vertices.push(_Vertex {
-                    position: [x, 0.0],
-                    color,
-                });
-                vertices.push(_Vertex {
-                    position: [x, height as f32],
-                    color,
-                });
-            }
-
-            // 水平线
-            let num_horizontal = (height as f32 / cell_size.y.ceil()) as i32;
-            for i in 0..=num_horizontal {
-                let y = i as f32 * cell_size.y;
-                vertices.push(_Vertex {
-                    position: [0.0, y],
-                    color,
-                });
-                vertices.push(_Vertex {
-                    position: [width as f32, y],
-                    color,
-                });
+        let add_grid_lines =
+            |vertices: &mut Vec<_Vertex>, cell_size: Vec2, origin: Vec2, color: [f32; 4]| {
+                // 网格线不一定从窗口原点开始
+                let offset = origin.rem_euclid(cell_size);
+                // 垂直线
+                let num_vertical = (width as f32 / cell_size.x).ceil() as i32;
+                for i in 0..=num_vertical {
+                    let x = offset.x + i as f32 * cell_size.x;
+                    vertices.push(_Vertex {
+                        position: [x, 0.0],
+                        color,
+                    });
+                    vertices.push(_Vertex {
+                        position: [x, height as f32],
+                        color,
+                    });
+                }
+
+                // 水平线
+                let num_horizontal = (height as f32 / cell_size.y.ceil()) as i32;
+                for i in 0..=num_horizontal {
+                    let y = offset.y + i as f32 * cell_size.y;
+                    vertices.push(_Vertex {
+                        position: [0.0, y],
+                        color,
+                    });
+                    vertices.push(_Vertex {
+                        position: [width as f32, y],
+                        color,
+                    });
+                }
+            };
+
+        // 每一层按注册顺序取颜色：碰撞网格红色，聚类网格蓝色，之后的层依次往下取
+        // 没有固定cell大小的层不画网格
+        for (i, (_, level)) in spaces.levels().enumerate() {
+            let color = LEVEL_COLORS[i % LEVEL_COLORS.len()];
+            if let Some(cell_size) = level.cell_size() {
+                add_grid_lines(&mut vertices, cell_size, level.cell_origin(), color);
             }
-        };
-
-        // 添加碰撞网格 (使用红色，较低透明度)
-        add_grid_lines(&mut vertices, collision_cell, [1.0, 0.0, 0.0, 0.3]);
-
-        // 添加聚类网格 (使用蓝色，较低透明度)
-        add_grid_lines(&mut vertices, clustering_cell, [0.0, 0.0, 1.0, 0.3]);
+        }
         // println!("vertices: {:?}", vertices);
         // 创建索引（每两个顶点形成一条线）
         let indices: Vec<u16> = (0..vertices.len() as u16).collect();
@@ -0,0 +1,266 @@
+use std::hash::BuildHasher;
+
+use glam::Vec2;
+
+use super::{dim::SpaceCell, Payload, SpaceMap, SpaceVector};
+
+/// 有大小的实体形状，会登记到它覆盖的每一个cell里，3D里 `Circle` 是球
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) enum Extent<V: SpaceVector = Vec2> {
+    Circle { center: V, radius: f32 },
+    Aabb { min: V, max: V },
+}
+
+impl<V: SpaceVector> Extent<V> {
+    fn bounds(&self) -> (V, V) {
+        match *self {
+            Extent::Circle { center, radius } => {
+                (center - V::splat(radius), center + V::splat(radius))
+            }
+            Extent::Aabb { min, max } => (min, max),
+        }
+    }
+
+    pub(super) fn center(&self) -> V {
+        match *self {
+            Extent::Circle { center, .. } => center,
+            Extent::Aabb { min, max } => (min + max) / 2.,
+        }
+    }
+
+    pub(super) fn translated(mut self, offset: V) -> Self {
+        self.translate(offset);
+        self
+    }
+
+    fn translate(&mut self, offset: V) {
+        match self {
+            Extent::Circle { center, .. } => *center += offset,
+            Extent::Aabb { min, max } => {
+                *min += offset;
+                *max += offset;
+            }
+        }
+    }
+
+    /// `pos` 到形状的距离的平方，在形状内部为0
+    pub(super) fn distance_squared(&self, pos: V) -> f32 {
+        match *self {
+            Extent::Circle { center, radius } => {
+                let d = ((center - pos).length() - radius).max(0.);
+                d * d
+            }
+            Extent::Aabb { min, max } => pos.clamp(min, max).distance_squared(pos),
+        }
+    }
+
+    /// 和轴对齐矩形 `[min, max]`（含边界）是否相交
+    pub(super) fn intersects_aabb(&self, min: V, max: V) -> bool {
+        match *self {
+            Extent::Circle { center, radius } => {
+                center.clamp(min, max).distance_squared(center) <= radius * radius
+            }
+            Extent::Aabb {
+                min: self_min,
+                max: self_max,
+            } => self_min.le_all(max) && min.le_all(self_max),
+        }
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 插入一个圆形实体，圆覆盖到的每个cell都能查到它
+    /// 和 `insert` 一样，`entity_id` 不能已经在map里
+    pub fn insert_circle(&mut self, entity_id: u32, center: V, radius: f32) {
+        self.insert_extent(entity_id, Extent::Circle { center, radius });
+    }
+
+    /// 插入一个轴对齐矩形实体 `[min, max]`
+    pub fn insert_aabb(&mut self, entity_id: u32, min: V, max: V) {
+        self.insert_extent(entity_id, Extent::Aabb { min, max });
+    }
+
+    pub(super) fn insert_extent(&mut self, entity_id: u32, extent: Extent<V>) {
+        // 环面世界里先把形状挪回世界范围内
+        let center = extent.center();
+        let extent = extent.translated(self.wrap_position(center) - center);
+        let (min_cell, max_cell) = self.extent_cells(&extent);
+        let mut cells = Vec::new();
+        self.for_each_cell(min_cell, max_cell, |_, cell_pos| cells.push(cell_pos));
+        for cell_pos in cells {
+            self.extent_map.entry(cell_pos).or_default().push(entity_id);
+        }
+        let id = entity_id as usize;
+        if id >= self.extents.len() {
+            self.extents.resize(id + 1, None);
+        }
+        self.extents[id] = Some(extent);
+        self.entity_count += 1;
+    }
+
+    /// 移除有大小的实体，返回它之前是否在map里
+    pub(super) fn remove_extent(&mut self, entity_id: u32) -> Option<Extent<V>> {
+        let extent = self.extents.get_mut(entity_id as usize)?.take()?;
+        let (min_cell, max_cell) = self.extent_cells(&extent);
+        let mut cells = Vec::new();
+        self.for_each_cell(min_cell, max_cell, |_, cell_pos| cells.push(cell_pos));
+        for cell_pos in cells {
+            let ids = self.extent_map.get_mut(&cell_pos).unwrap();
+            let index = ids.iter().position(|id| *id == entity_id).unwrap();
+            ids.swap_remove(index);
+        }
+        self.entity_count -= 1;
+        Some(extent)
+    }
+
+    /// 整体平移一个有大小的实体，`entity_id` 不是这种实体时返回 `false`
+    pub(super) fn translate_extent(&mut self, entity_id: u32, offset: V) -> bool {
+        let Some(mut extent) = self.remove_extent(entity_id) else {
+            return false;
+        };
+        extent.translate(offset);
+        self.insert_extent(entity_id, extent);
+        true
+    }
+
+    /// 访问和cell矩形 `[min_cell, max_cell]`（未取模）有重叠的所有有大小的实体，每个只访问一次
+    /// 实体只在它和查询范围重叠部分的左下角cell被报告，所以平面世界里不需要额外的去重集合
+    pub(super) fn for_each_extent(
+        &self,
+        min_cell: V::Cell,
+        max_cell: V::Cell,
+        mut f: impl FnMut(u32, &Extent<V>),
+    ) {
+        if self.extent_map.is_empty() {
+            return;
+        }
+        let mut dedup = self.extent_dedup();
+        self.for_each_cell(min_cell, max_cell, |raw_cell, cell_pos| {
+            let Some(ids) = self.extent_map.get(&cell_pos) else {
+                return;
+            };
+            for id in ids {
+                let extent = self.extents[*id as usize].as_ref().unwrap();
+                let first_visit = match dedup.as_mut() {
+                    Some(seen) => seen.insert(*id),
+                    None => self.extent_cells(extent).0.max(min_cell) == raw_cell,
+                };
+                if first_visit {
+                    f(*id, extent);
+                }
+            }
+        });
+    }
+
+    /// 形状覆盖的cell矩形（未取模）
+    fn extent_cells(&self, extent: &Extent<V>) -> (V::Cell, V::Cell) {
+        let (min, max) = extent.bounds();
+        (self.raw_cell_index(min), self.raw_cell_index(max))
+    }
+}
+
+#[test]
+fn large_circle_is_visible_from_neighbouring_cells() {
+    let mut map = super::Collision::new(Vec2::new(20., 20.));
+    map.insert_circle(0, Vec2::new(18., 10.), 15.);
+    map.insert(1, Vec2::new(30., 10.));
+    map.insert(2, Vec2::new(-30., 10.));
+
+    // 查询点在隔壁cell，圆心不在查询范围内但圆和查询圆相交
+    let mut ids = map.query_radius(Vec2::new(36., 10.), 4.);
+    ids.sort();
+    assert_eq!(ids, vec![0]);
+    let mut ids = map.query_radius(Vec2::new(30., 10.), 1.);
+    ids.sort();
+    assert_eq!(ids, vec![0, 1]);
+    // 覆盖很多cell时结果也不会重复
+    let mut ids = map.query_radius(Vec2::new(0., 0.), 100.);
+    ids.sort();
+    assert_eq!(ids, vec![0, 1, 2]);
+    assert_eq!(
+        map.query_aabb(Vec2::new(31., 5.), Vec2::new(60., 60.)),
+        vec![0]
+    );
+    assert_eq!(
+        map.query_aabb(Vec2::new(34., 5.), Vec2::new(60., 60.)),
+        Vec::<u32>::new()
+    );
+    assert_eq!(map.nearest(Vec2::new(40., 10.)), Some(0));
+}
+
+#[test]
+fn extent_queries_match_brute_force() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(12);
+    let mut map = super::Collision::new(Vec2::new(25., 25.));
+    let mut extents = Vec::new();
+    for i in 0..300 {
+        let center = Vec2::new(rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0));
+        let extent = match i % 3 {
+            0 => Extent::Circle {
+                center,
+                radius: rng.gen_range(0.0..60.0),
+            },
+            1 => {
+                let half = Vec2::new(rng.gen_range(0.0..50.0), rng.gen_range(0.0..50.0));
+                Extent::Aabb {
+                    min: center - half,
+                    max: center + half,
+                }
+            }
+            // 普通的点实体
+            _ => Extent::Aabb {
+                min: center,
+                max: center,
+            },
+        };
+        match extent {
+            Extent::Circle { center, radius } => map.insert_circle(i, center, radius),
+            Extent::Aabb { min, max } if i % 3 == 1 => map.insert_aabb(i, min, max),
+            Extent::Aabb { min, .. } => map.insert(i, min),
+        }
+        extents.push(extent);
+    }
+    // 移动和删除一部分
+    for i in (0..300).step_by(7) {
+        let offset = Vec2::new(rng.gen_range(-40.0..40.0), rng.gen_range(-40.0..40.0));
+        let old = extents[i].bounds().0;
+        extents[i].translate(offset);
+        map.update_position(i as u32, old, old + offset);
+    }
+    let mut alive = vec![true; extents.len()];
+    for i in (3..300).step_by(11) {
+        assert!(map.remove(i as u32));
+        alive[i] = false;
+    }
+    assert_eq!(map.entity_count, alive.iter().filter(|a| **a).count());
+
+    for _ in 0..50 {
+        let pos = Vec2::new(rng.gen_range(-250.0..250.0), rng.gen_range(-250.0..250.0));
+        let r = rng.gen_range(0.0..80.0);
+        let expected: Vec<u32> = (0..extents.len())
+            .filter(|i| alive[*i] && extents[*i].distance_squared(pos) <= r * r)
+            .map(|i| i as u32)
+            .collect();
+        let mut got = map.query_radius(pos, r);
+        got.sort();
+        assert_eq!(got, expected);
+
+        let max = pos + Vec2::new(rng.gen_range(0.0..90.0), rng.gen_range(0.0..90.0));
+        let expected: Vec<u32> = (0..extents.len())
+            .filter(|i| alive[*i] && extents[*i].intersects_aabb(pos, max))
+            .map(|i| i as u32)
+            .collect();
+        let mut got = map.query_aabb(pos, max);
+        got.sort();
+        assert_eq!(got, expected);
+
+        let nearest = (0..extents.len())
+            .filter(|i| alive[*i])
+            .map(|i| (extents[i].distance_squared(pos), i as u32))
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .map(|(_, i)| i);
+        assert_eq!(map.nearest(pos), nearest);
+    }
+}
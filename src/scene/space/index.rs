@@ -0,0 +1,289 @@
+use std::hash::BuildHasher;
+
+use glam::Vec2;
+
+#[cfg(feature = "serde")]
+use super::snapshot::{SnapshotError, SpaceMapSnapshot};
+use super::{dense::DenseGrid, dim::SpaceVector, IndexPayload, SpaceMap, SpaceStats};
+
+/// 空间索引的统一接口，boid系统只依赖它，方便换不同的数据结构做对比
+/// 实体id约定为实体在实体数组中的下标，`V` 是位置向量的类型，默认2D
+pub trait SpatialIndex<V: SpaceVector = Vec2> {
+    /// 均匀网格的cell大小，没有固定cell的结构返回 `None`
+    fn cell_size(&self) -> Option<V> {
+        None
+    }
+    /// 网格cell坐标的原点
+    fn cell_origin(&self) -> V {
+        V::ZERO
+    }
+    fn clear(&mut self);
+    /// 用全部实体的位置重建，`positions[i]` 的id为 `i`
+    fn build(&mut self, positions: &[V]) {
+        self.clear();
+        for (i, position) in positions.iter().enumerate() {
+            self.insert(i as u32, *position);
+        }
+    }
+    fn insert(&mut self, entity_id: u32, position: V);
+    /// `position` 是实体最后一次写入时的位置，按位置查找实体的结构用它定位
+    fn remove(&mut self, entity_id: u32, position: V) -> bool;
+    fn update_position(&mut self, entity_id: u32, old: V, new: V) {
+        self.remove(entity_id, old);
+        self.insert(entity_id, new);
+    }
+    /// 每帧批量更新全部实体，默认直接重建，支持增量更新的结构可以覆盖
+    fn update_positions(&mut self, old: &[V], new: &[V]) {
+        debug_assert_eq!(old.len(), new.len());
+        self.build(new);
+    }
+    /// 实体数组按 `order` 重排之后改写id，新id `i` 原来是 `order[i]`
+    /// `positions` 是重排后的位置，默认直接重建
+    fn remap_ids(&mut self, order: &[u32], positions: &[V]) {
+        debug_assert_eq!(order.len(), positions.len());
+        self.build(positions);
+    }
+    /// 存了速度的结构在位置更新之后写入 `velocities[i]`，默认什么都不做
+    fn update_velocities(&mut self, _velocities: &[V]) {}
+    fn query_radius(&self, pos: V, r: f32) -> Vec<u32>;
+    /// 对 `pos` 周围 `r` 以内的每个实体调用 `f(id, offset, velocity)`，`offset` 是从 `pos` 指向实体的向量
+    /// 默认用 `query_radius` 再回到 `positions` / `velocities` 里取，cell里存了这些数据的结构可以覆盖
+    fn for_each_neighbor(
+        &self,
+        pos: V,
+        r: f32,
+        positions: &[V],
+        velocities: &[V],
+        f: &mut dyn FnMut(u32, V, V),
+    ) {
+        for id in self.query_radius(pos, r) {
+            let offset = self.displacement(pos, positions[id as usize]);
+            f(id, offset, velocities[id as usize]);
+        }
+    }
+    fn query_aabb(&self, min: V, max: V) -> Vec<u32>;
+    fn nearest(&self, pos: V) -> Option<u32>;
+    /// 所有距离不超过 `dist` 的无序实体对 `(a, b, 距离)`，`a < b`
+    /// `positions[i]` 是实体 `i` 在索引中的位置，默认对每个实体做一次范围查询
+    fn pairs_within(&self, positions: &[V], dist: f32) -> Vec<(u32, u32, f32)> {
+        let mut pairs = Vec::new();
+        for (i, pos) in positions.iter().enumerate() {
+            for j in self.query_radius(*pos, dist) {
+                if j as usize > i {
+                    let d = self.displacement(*pos, positions[j as usize]).length();
+                    pairs.push((i as u32, j, d));
+                }
+            }
+        }
+        pairs
+    }
+    /// 从 `from` 指向 `to` 的向量，环面世界的实现返回最近镜像
+    fn displacement(&self, from: V, to: V) -> V {
+        to - from
+    }
+    /// 占用统计，只有 `SpaceMap` 支持
+    fn stats(&self) -> Option<SpaceStats<V>> {
+        None
+    }
+    /// 换一个cell大小重建，不支持的结构返回 `false`
+    fn set_cell_size(&mut self, _cell_size: V) -> bool {
+        false
+    }
+    /// 保存完整状态用于调试，只有 `SpaceMap` 支持
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> Option<SpaceMapSnapshot<V>> {
+        None
+    }
+    /// 用快照替换当前内容
+    #[cfg(feature = "serde")]
+    fn restore(&mut self, _snapshot: &SpaceMapSnapshot<V>) -> Result<(), SnapshotError> {
+        Err(SnapshotError::Unsupported)
+    }
+    /// 用快照创建一个配置相同的新索引，当前内容不变
+    #[cfg(feature = "serde")]
+    fn restored(&self, _snapshot: &SpaceMapSnapshot<V>) -> Result<Self, SnapshotError>
+    where
+        Self: Sized,
+    {
+        Err(SnapshotError::Unsupported)
+    }
+}
+
+/// 接口只给出实体id，payload用 `IndexPayload::from_id` 创建，附带的速度由 `update_velocities` 写入
+impl<T, S: BuildHasher + Clone, P: IndexPayload<V>, V: SpaceVector> SpatialIndex<V>
+    for SpaceMap<T, S, P, V>
+{
+    fn cell_size(&self) -> Option<V> {
+        Some(self.cell_size)
+    }
+    fn cell_origin(&self) -> V {
+        SpaceMap::cell_origin(self)
+    }
+    fn clear(&mut self) {
+        SpaceMap::clear(self);
+    }
+    #[cfg(feature = "rayon")]
+    fn build(&mut self, positions: &[V]) {
+        SpaceMap::par_build(self, positions);
+    }
+    fn insert(&mut self, entity_id: u32, position: V) {
+        SpaceMap::insert(self, P::from_id(entity_id), position);
+    }
+    fn remove(&mut self, entity_id: u32, _position: V) -> bool {
+        SpaceMap::remove(self, entity_id)
+    }
+    fn update_position(&mut self, entity_id: u32, old: V, new: V) {
+        SpaceMap::update_position(self, P::from_id(entity_id), old, new);
+    }
+    fn update_positions(&mut self, old: &[V], new: &[V]) {
+        if self.reuses_buckets() {
+            self.build(new);
+            return;
+        }
+        for (i, (old, new)) in old.iter().zip(new.iter()).enumerate() {
+            SpaceMap::update_position(self, P::from_id(i as u32), *old, *new);
+        }
+    }
+    fn remap_ids(&mut self, order: &[u32], _positions: &[V]) {
+        SpaceMap::remap_ids(self, order);
+    }
+    fn update_velocities(&mut self, velocities: &[V]) {
+        SpaceMap::update_velocities(self, velocities);
+    }
+    fn query_radius(&self, pos: V, r: f32) -> Vec<u32> {
+        SpaceMap::query_radius(self, pos, r)
+    }
+    /// 点实体的offset和速度直接从cell里读，payload没存速度时才回到 `velocities` 里取
+    fn for_each_neighbor(
+        &self,
+        pos: V,
+        r: f32,
+        _positions: &[V],
+        velocities: &[V],
+        f: &mut dyn FnMut(u32, V, V),
+    ) {
+        self.for_each_extent_in_radius(pos, r, |id, offset| f(id, offset, velocities[id as usize]));
+        self.for_each_payload_in_radius(pos, r, |payload, offset| {
+            let velocity = payload
+                .velocity()
+                .unwrap_or_else(|| velocities[payload.id() as usize]);
+            f(payload.id(), offset, velocity)
+        });
+    }
+    fn query_aabb(&self, min: V, max: V) -> Vec<u32> {
+        SpaceMap::query_aabb(self, min, max)
+    }
+    fn nearest(&self, pos: V) -> Option<u32> {
+        SpaceMap::nearest(self, pos)
+    }
+    fn pairs_within(&self, _positions: &[V], dist: f32) -> Vec<(u32, u32, f32)> {
+        SpaceMap::pairs_within(self, dist)
+    }
+    fn displacement(&self, from: V, to: V) -> V {
+        SpaceMap::displacement(self, from, to)
+    }
+    fn stats(&self) -> Option<SpaceStats<V>> {
+        Some(SpaceMap::stats(self))
+    }
+    fn set_cell_size(&mut self, cell_size: V) -> bool {
+        SpaceMap::set_cell_size(self, cell_size);
+        true
+    }
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> Option<SpaceMapSnapshot<V>> {
+        Some(SpaceMap::snapshot(self))
+    }
+    #[cfg(feature = "serde")]
+    fn restore(&mut self, snapshot: &SpaceMapSnapshot<V>) -> Result<(), SnapshotError> {
+        SpaceMap::restore(self, snapshot)
+    }
+    #[cfg(feature = "serde")]
+    fn restored(&self, snapshot: &SpaceMapSnapshot<V>) -> Result<Self, SnapshotError> {
+        SpaceMap::restored(self, snapshot)
+    }
+}
+
+impl<T> SpatialIndex for DenseGrid<T> {
+    fn cell_size(&self) -> Option<Vec2> {
+        Some(DenseGrid::cell_size(self))
+    }
+    fn cell_origin(&self) -> Vec2 {
+        self.origin()
+    }
+    fn clear(&mut self) {
+        DenseGrid::clear(self);
+    }
+    fn build(&mut self, positions: &[Vec2]) {
+        self.rebuild(positions.iter().enumerate().map(|(i, p)| (i as u32, *p)));
+    }
+    /// 稠密网格为整帧重建设计，单个插入/删除都会触发一次重建
+    fn insert(&mut self, entity_id: u32, position: Vec2) {
+        DenseGrid::insert(self, entity_id, position);
+        DenseGrid::build(self);
+    }
+    fn remove(&mut self, entity_id: u32, _position: Vec2) -> bool {
+        let removed = DenseGrid::remove(self, entity_id);
+        if removed {
+            DenseGrid::build(self);
+        }
+        removed
+    }
+    fn query_radius(&self, pos: Vec2, r: f32) -> Vec<u32> {
+        DenseGrid::query_radius(self, pos, r)
+    }
+    fn query_aabb(&self, min: Vec2, max: Vec2) -> Vec<u32> {
+        DenseGrid::query_aabb(self, min, max)
+    }
+    fn nearest(&self, pos: Vec2) -> Option<u32> {
+        DenseGrid::nearest(self, pos)
+    }
+}
+
+#[test]
+fn backends_agree_through_trait() {
+    use rand::{Rng, SeedableRng};
+    fn check<A: SpatialIndex, B: SpatialIndex>(a: &A, b: &B, pos: Vec2) {
+        let sorted = |mut ids: Vec<u32>| {
+            ids.sort();
+            ids
+        };
+        assert_eq!(
+            sorted(a.query_radius(pos, 90.)),
+            sorted(b.query_radius(pos, 90.))
+        );
+        let max = pos + Vec2::new(120., 70.);
+        assert_eq!(
+            sorted(a.query_aabb(pos, max)),
+            sorted(b.query_aabb(pos, max))
+        );
+        assert_eq!(a.nearest(pos), b.nearest(pos));
+    }
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(10);
+    let mut hash = super::Collision::new(Vec2::new(40., 40.));
+    let mut dense = DenseGrid::<super::CollisionMarker>::new(
+        Vec2::ZERO,
+        Vec2::new(800., 600.),
+        Vec2::new(40., 40.),
+    );
+    let mut positions: Vec<Vec2> = (0..600)
+        .map(|_| Vec2::new(rng.gen_range(0.0..800.0), rng.gen_range(0.0..600.0)))
+        .collect();
+    SpatialIndex::build(&mut hash, &positions);
+    SpatialIndex::build(&mut dense, &positions);
+    for _ in 0..5 {
+        let old = positions.clone();
+        for p in positions.iter_mut() {
+            *p += Vec2::new(rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0));
+        }
+        hash.update_positions(&old, &positions);
+        dense.update_positions(&old, &positions);
+        for _ in 0..20 {
+            let pos = Vec2::new(rng.gen_range(0.0..800.0), rng.gen_range(0.0..600.0));
+            check(&hash, &dense, pos);
+        }
+    }
+    assert!(SpatialIndex::remove(&mut hash, 3, positions[3]));
+    assert!(SpatialIndex::remove(&mut dense, 3, positions[3]));
+    check(&hash, &dense, positions[3]);
+}
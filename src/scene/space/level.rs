@@ -0,0 +1,295 @@
+use std::any::{Any, TypeId};
+
+use glam::Vec2;
+
+#[cfg(feature = "serde")]
+use super::snapshot::{SnapshotError, SpaceMapSnapshot};
+use super::{
+    tune::{CellRetune, CellTuning},
+    Space, SpaceMap, SpatialIndex,
+};
+
+/// `Space` 中的一层，任何 `SpatialIndex` 都可以作为一层，按具体类型区分
+/// 要求 `Send + Sync`，boid的force循环会在多个线程里同时查询
+pub trait SpaceLevel: SpatialIndex + Any + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// 用快照创建同一种类型的新层，当前层不变
+    #[cfg(feature = "serde")]
+    fn restored_level(
+        &self,
+        snapshot: &SpaceMapSnapshot,
+    ) -> Result<Box<dyn SpaceLevel>, SnapshotError>;
+}
+
+impl<I: SpatialIndex + Any + Send + Sync> SpaceLevel for I {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+    #[cfg(feature = "serde")]
+    fn restored_level(
+        &self,
+        snapshot: &SpaceMapSnapshot,
+    ) -> Result<Box<dyn SpaceLevel>, SnapshotError> {
+        Ok(Box::new(SpatialIndex::restored(self, snapshot)?))
+    }
+}
+
+pub(super) struct LevelEntry {
+    pub(super) name: &'static str,
+    type_id: TypeId,
+    pub(super) map: Box<dyn SpaceLevel>,
+    // 开启运行时调整cell大小时的规则
+    tuning: Option<CellTuning>,
+}
+
+/// 每隔多少次 `auto_tune` 检查一次占用统计
+const TUNE_INTERVAL_FRAMES: u32 = 120;
+
+impl Space {
+    /// 注册一层，同一种索引类型只能注册一次
+    pub fn with_level<L: SpaceLevel>(mut self, name: &'static str, map: L) -> Self {
+        self.add_level(name, map);
+        self
+    }
+
+    pub fn add_level<L: SpaceLevel>(&mut self, name: &'static str, map: L) {
+        let type_id = TypeId::of::<L>();
+        assert!(
+            self.levels
+                .iter()
+                .all(|l| l.type_id != type_id && l.name != name),
+            "space level {} registered twice",
+            name
+        );
+        self.levels.push(LevelEntry {
+            name,
+            type_id,
+            map: Box::new(map),
+            tuning: None,
+        });
+    }
+
+    /// 按索引的具体类型取某一层，例如 `space.get_index::<DenseGrid<CollisionMarker>>()`
+    pub fn get_index<I: SpaceLevel>(&self) -> Option<&I> {
+        let type_id = TypeId::of::<I>();
+        let entry = self.levels.iter().find(|l| l.type_id == type_id)?;
+        entry.map.as_any().downcast_ref()
+    }
+
+    pub fn get_index_mut<I: SpaceLevel>(&mut self) -> Option<&mut I> {
+        let type_id = TypeId::of::<I>();
+        let entry = self.levels.iter_mut().find(|l| l.type_id == type_id)?;
+        entry.map.as_any_mut().downcast_mut()
+    }
+
+    pub fn index<I: SpaceLevel>(&self) -> &I {
+        self.get_index::<I>().expect("space level not registered")
+    }
+
+    pub fn index_mut<I: SpaceLevel>(&mut self) -> &mut I {
+        self.get_index_mut::<I>()
+            .expect("space level not registered")
+    }
+
+    /// 按marker类型取只存id的 `SpaceMap` 层，例如 `space.level::<CollisionMarker>()`
+    /// 存了其它payload的层（比如 `Clustering`）用 `index` 按完整类型取
+    pub fn get_level<M: 'static>(&self) -> Option<&SpaceMap<M>> {
+        self.get_index::<SpaceMap<M>>()
+    }
+
+    pub fn get_level_mut<M: 'static>(&mut self) -> Option<&mut SpaceMap<M>> {
+        self.get_index_mut::<SpaceMap<M>>()
+    }
+
+    pub fn level<M: 'static>(&self) -> &SpaceMap<M> {
+        self.index::<SpaceMap<M>>()
+    }
+
+    pub fn level_mut<M: 'static>(&mut self) -> &mut SpaceMap<M> {
+        self.index_mut::<SpaceMap<M>>()
+    }
+
+    /// 按注册时的名字取某一层
+    pub fn level_by_name(&self, name: &str) -> Option<&dyn SpaceLevel> {
+        let entry = self.levels.iter().find(|l| l.name == name)?;
+        Some(entry.map.as_ref())
+    }
+
+    pub fn level_by_name_mut(&mut self, name: &str) -> Option<&mut dyn SpaceLevel> {
+        let entry = self.levels.iter_mut().find(|l| l.name == name)?;
+        Some(entry.map.as_mut())
+    }
+
+    /// 按注册顺序遍历所有层
+    pub fn levels(&self) -> impl Iterator<Item = (&'static str, &dyn SpaceLevel)> {
+        self.levels.iter().map(|l| (l.name, l.map.as_ref()))
+    }
+
+    /// 把实体插入到所有层
+    pub fn insert(&mut self, entity_id: u32, position: Vec2) {
+        for level in self.levels.iter_mut() {
+            level.map.insert(entity_id, position);
+        }
+    }
+
+    pub fn update_position(&mut self, entity_id: u32, old: Vec2, new: Vec2) {
+        for level in self.levels.iter_mut() {
+            level.map.update_position(entity_id, old, new);
+        }
+    }
+
+    /// 批量更新所有层，`old[i]` / `new[i]` 是实体 `i` 的上一次位置和当前位置
+    pub fn update_positions(&mut self, old: &[Vec2], new: &[Vec2]) {
+        for level in self.levels.iter_mut() {
+            level.map.update_positions(old, new);
+        }
+    }
+
+    /// 位置更新之后把 `velocities[i]` 写进存了速度的层
+    pub fn update_velocities(&mut self, velocities: &[Vec2]) {
+        for level in self.levels.iter_mut() {
+            level.map.update_velocities(velocities);
+        }
+    }
+
+    /// 实体数组按 `order` 重排之后更新所有层，`positions` 是重排后每个实体在空间里的位置
+    pub fn remap_ids(&mut self, order: &[u32], positions: &[Vec2]) {
+        for level in self.levels.iter_mut() {
+            level.map.remap_ids(order, positions);
+        }
+    }
+
+    pub fn remove(&mut self, entity_id: u32, position: Vec2) {
+        for level in self.levels.iter_mut() {
+            level.map.remove(entity_id, position);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for level in self.levels.iter_mut() {
+            level.map.clear();
+        }
+    }
+
+    /// 给某一层开启运行时调整cell大小，只对支持 `set_cell_size` 的索引生效
+    pub fn set_tuning(&mut self, name: &str, tuning: CellTuning) {
+        if let Some(level) = self.levels.iter_mut().find(|l| l.name == name) {
+            level.tuning = Some(tuning);
+        }
+    }
+
+    /// 每帧调用，每 `TUNE_INTERVAL_FRAMES` 帧看一次占用统计
+    /// cell太粗或太细的层用新的cell大小整体重建，返回这一次换过cell大小的层
+    pub fn auto_tune(&mut self) -> Vec<CellRetune> {
+        let mut retunes = Vec::new();
+        self.tune_frames += 1;
+        if self.tune_frames < TUNE_INTERVAL_FRAMES {
+            return retunes;
+        }
+        self.tune_frames = 0;
+        for level in self.levels.iter_mut() {
+            let Some(tuning) = level.tuning else {
+                continue;
+            };
+            let Some(stats) = level.map.stats() else {
+                continue;
+            };
+            let Some(cell_size) = tuning.suggest(&stats) else {
+                continue;
+            };
+            if level.map.set_cell_size(cell_size) {
+                retunes.push(CellRetune {
+                    level: level.name,
+                    old_cell_size: stats.cell_size,
+                    new_cell_size: level.map.cell_size().unwrap_or(cell_size),
+                    mean: stats.mean,
+                });
+            }
+        }
+        retunes
+    }
+}
+
+#[test]
+fn space_levels_are_typed_and_filled_together() {
+    use super::{Clustering, Collision, CollisionMarker};
+    #[derive(Default)]
+    struct ViewMarker;
+
+    let mut space = Space::default()
+        .with_level("collision", Collision::new(Vec2::new(10., 10.)))
+        .with_level("clustering", Clustering::new(Vec2::new(50., 50.)))
+        .with_level("view", SpaceMap::<ViewMarker>::new(Vec2::new(200., 200.)));
+    space.insert(0, Vec2::new(5., 5.));
+    space.insert(1, Vec2::new(15., 5.));
+
+    let ids = |grid: Option<&super::IndexGrid>| grid.unwrap().get_entities().to_vec();
+    assert_eq!(
+        ids(space.level::<CollisionMarker>().query(Vec2::ZERO)),
+        vec![0]
+    );
+    assert_eq!(
+        space
+            .index::<Clustering>()
+            .query(Vec2::ZERO)
+            .unwrap()
+            .ids()
+            .collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert_eq!(
+        ids(space.level::<ViewMarker>().query(Vec2::ZERO)),
+        vec![0, 1]
+    );
+    assert_eq!(
+        space.level_by_name("view").unwrap().cell_size(),
+        Some(Vec2::new(200., 200.))
+    );
+    let names: Vec<&str> = space.levels().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["collision", "clustering", "view"]);
+
+    space.update_position(1, Vec2::new(15., 5.), Vec2::new(60., 5.));
+    assert_eq!(
+        space
+            .index::<Clustering>()
+            .query(Vec2::ZERO)
+            .unwrap()
+            .ids()
+            .collect::<Vec<_>>(),
+        vec![0]
+    );
+    space.clear();
+    assert!(space.level::<CollisionMarker>().query(Vec2::ZERO).is_none());
+}
+
+#[test]
+fn auto_tune_reports_retuned_levels() {
+    use super::Collision;
+    let mut space = Space::default().with_level("collision", Collision::new(Vec2::new(4., 4.)));
+    // 每个实体单独占一个cell，4x4的cell太细
+    for i in 0..100 {
+        space.insert(i, Vec2::new(i as f32 * 10., 0.));
+    }
+    space.set_tuning("collision", CellTuning::for_radius(8., None));
+    for _ in 1..TUNE_INTERVAL_FRAMES {
+        assert!(space.auto_tune().is_empty());
+    }
+    let retunes = space.auto_tune();
+    assert_eq!(
+        retunes,
+        vec![CellRetune {
+            level: "collision",
+            old_cell_size: Vec2::new(4., 4.),
+            new_cell_size: Vec2::new(8., 8.),
+            mean: 1.,
+        }]
+    );
+    assert_eq!(
+        space.level_by_name("collision").unwrap().cell_size(),
+        Some(Vec2::new(8., 8.))
+    );
+}
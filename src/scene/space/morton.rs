@@ -0,0 +1,148 @@
+use std::hash::BuildHasher;
+
+use glam::{IVec2, Vec2};
+
+use super::{IndexPayload, SpaceMap, SpaceVector};
+
+/// cell坐标的Z序（Morton）编码，x和y的二进制位交错排列
+/// 编码相邻的cell在空间里大多也相邻，负坐标先平移到无符号范围
+pub fn morton_key(cell: IVec2) -> u64 {
+    spread(cell.x as u32 ^ 0x8000_0000) | spread(cell.y as u32 ^ 0x8000_0000) << 1
+}
+
+/// 把32位数的每一位隔开一位放进64位
+fn spread(v: u32) -> u64 {
+    let mut v = v as u64;
+    v = (v | v << 16) & 0x0000_ffff_0000_ffff;
+    v = (v | v << 8) & 0x00ff_00ff_00ff_00ff;
+    v = (v | v << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | v << 2) & 0x3333_3333_3333_3333;
+    (v | v << 1) & 0x5555_5555_5555_5555
+}
+
+/// 实体按所在cell的Z序排列后的顺序，新下标 `i` 放原来的实体 `order[i]`
+/// 同一个cell里保持原来的先后
+pub fn morton_order(positions: &[Vec2], cell_size: Vec2, origin: Vec2) -> Vec<u32> {
+    let mut keyed: Vec<(u64, u32)> = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let cell_pos = ((*position - origin) / cell_size).floor().as_ivec2();
+            (morton_key(cell_pos), i as u32)
+        })
+        .collect();
+    keyed.sort_unstable();
+    keyed.into_iter().map(|(_, i)| i).collect()
+}
+
+/// 按 `order` 重排数组，`values` 的前 `order.len()` 个元素参与重排，后面的不动
+pub fn reorder_by<X: Copy>(values: &mut [X], order: &[u32]) {
+    let moved: Vec<X> = order.iter().map(|i| values[*i as usize]).collect();
+    values[..moved.len()].copy_from_slice(&moved);
+}
+
+/// `order` 的逆映射，原来的id -> 新id
+fn new_ids(order: &[u32]) -> Vec<u32> {
+    let mut new_of_old = vec![0; order.len()];
+    for (new, old) in order.iter().enumerate() {
+        new_of_old[*old as usize] = new as u32;
+    }
+    new_of_old
+}
+
+impl<T, S: BuildHasher + Clone, P: IndexPayload<V>, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 实体数组按 `order` 重排之后原地改写map里的id，不用重建
+    /// 只有id小于 `order.len()` 的实体会被改写，cell、位置和payload附带的数据都不变
+    pub fn remap_ids(&mut self, order: &[u32]) {
+        let new_of_old = new_ids(order);
+        let remap = |id: &mut u32| {
+            if let Some(new) = new_of_old.get(*id as usize) {
+                *id = *new;
+            }
+        };
+        self.map.values_mut().for_each(|grid| {
+            grid.payloads.iter_mut().for_each(|payload| {
+                let mut id = payload.id();
+                remap(&mut id);
+                payload.set_id(id);
+            })
+        });
+        if let Some(border_map) = self.border_layer_map.as_mut() {
+            border_map
+                .values_mut()
+                .for_each(|grid| grid.payloads.iter_mut().for_each(remap));
+        }
+        self.extent_map
+            .values_mut()
+            .for_each(|ids| ids.iter_mut().for_each(remap));
+        if self.entity_slots.len() < order.len() {
+            self.entity_slots.resize(order.len(), None);
+        }
+        reorder_by(&mut self.entity_slots, order);
+        if self.extents.len() < order.len() {
+            self.extents.resize(order.len(), None);
+        }
+        reorder_by(&mut self.extents, order);
+    }
+}
+
+#[test]
+fn morton_order_groups_cells_and_remap_matches_rebuild() {
+    use rand::{Rng, SeedableRng};
+    assert_eq!(
+        morton_key(IVec2::new(0, 0)) + 1,
+        morton_key(IVec2::new(1, 0))
+    );
+    assert_eq!(
+        morton_key(IVec2::new(0, 0)) + 2,
+        morton_key(IVec2::new(0, 1))
+    );
+    assert!(morton_key(IVec2::new(-1, -1)) < morton_key(IVec2::new(0, 0)));
+
+    let cell_size = Vec2::new(20., 20.);
+    let positions = [
+        Vec2::new(45., 5.),
+        Vec2::new(5., 5.),
+        Vec2::new(25., 25.),
+        Vec2::new(6., 6.),
+        Vec2::new(25., 5.),
+    ];
+    assert_eq!(
+        morton_order(&positions, cell_size, Vec2::ZERO),
+        vec![1, 3, 4, 2, 0]
+    );
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(25);
+    let mut positions: Vec<Vec2> = (0..600)
+        .map(|_| Vec2::new(rng.gen_range(-50.0..450.0), rng.gen_range(-50.0..350.0)))
+        .collect();
+    let mut map = super::Collision::new(cell_size);
+    map.with_border_layer(2., 6.);
+    for (i, p) in positions.iter().enumerate() {
+        map.insert(i as u32, *p);
+    }
+    map.insert_circle(600, Vec2::new(100., 100.), 30.);
+    let order = morton_order(&positions, cell_size, Vec2::ZERO);
+    reorder_by(&mut positions, &order);
+    map.remap_ids(&order);
+
+    let mut rebuilt = super::Collision::new(cell_size);
+    rebuilt.with_border_layer(2., 6.);
+    for (i, p) in positions.iter().enumerate() {
+        rebuilt.insert(i as u32, *p);
+    }
+    rebuilt.insert_circle(600, Vec2::new(100., 100.), 30.);
+    for _ in 0..50 {
+        let pos = Vec2::new(rng.gen_range(-50.0..450.0), rng.gen_range(-50.0..350.0));
+        let mut got = map.query_radius(pos, 15.);
+        let mut expected = rebuilt.query_radius(pos, 15.);
+        got.sort();
+        expected.sort();
+        assert_eq!(got, expected);
+    }
+    // 重排后的位置索引仍然能找到每个实体
+    for i in 0..20 {
+        assert!(map.remove(i));
+    }
+    assert_eq!(map.entity_count(), 581);
+}
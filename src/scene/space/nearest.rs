@@ -0,0 +1,233 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    hash::BuildHasher,
+};
+
+use super::{
+    dim::{SpaceCell, SpaceVector},
+    Payload, SpaceMap,
+};
+
+/// 候选实体，按距离排序，距离相同时按id排序保证结果稳定
+#[derive(Clone, Copy)]
+struct Candidate {
+    dist2: f32,
+    id: u32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist2
+            .total_cmp(&other.dist2)
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+/// `nearest_k` 搜索过程中的状态
+struct NearestSearch<C> {
+    k: usize,
+    max_r2: f32,
+    // 大顶堆，堆顶是当前第k近的候选
+    best: BinaryHeap<Candidate>,
+    // 有大小的实体会出现在多个cell里，记住已经看过的
+    seen_extents: HashSet<u32>,
+    // 环面世界里外圈会绕回到已经看过的cell
+    visited_cells: Option<HashSet<C>>,
+    // 已经看过的实体数
+    seen: usize,
+}
+
+impl<C> NearestSearch<C> {
+    fn push(&mut self, candidate: Candidate) {
+        if candidate.dist2 > self.max_r2 {
+            return;
+        }
+        if self.best.len() < self.k {
+            self.best.push(candidate);
+        } else if candidate < *self.best.peek().unwrap() {
+            self.best.pop();
+            self.best.push(candidate);
+        }
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 找出距离 `pos` 最近的 `k` 个实体（距离不超过 `max_radius`），按距离从近到远返回
+    /// 从所在cell开始一圈一圈向外扩，当第k近的距离已经不可能被更外圈的实体超过时停止
+    /// 已经扫过的正方形（3D是立方体）比map里的cell还多时，剩下的cell直接逐个扫，不再一圈圈地查空cell
+    /// 有大小的实体按 `pos` 到形状的距离计算
+    pub fn nearest_k(&self, pos: V, k: usize, max_radius: f32) -> Vec<u32> {
+        if k == 0 || self.entity_count == 0 {
+            return Vec::new();
+        }
+        let pos = self.wrap_position(pos);
+        let center = self.raw_cell_index(pos);
+        let mut search = NearestSearch {
+            k,
+            max_r2: max_radius * max_radius,
+            best: BinaryHeap::with_capacity(k + 1),
+            seen_extents: HashSet::new(),
+            visited_cells: self.is_wrapped().then(HashSet::new),
+            seen: 0,
+        };
+        let mut ring = 0;
+        loop {
+            let side = 2 * ring as usize + 1;
+            if side.pow(V::Cell::DIM) > self.map.len() + self.extent_map.len() {
+                let extent_only = self
+                    .extent_map
+                    .keys()
+                    .filter(|cell_pos| !self.map.contains_key(cell_pos));
+                for cell_pos in self.map.keys().chain(extent_only) {
+                    // 环面世界由 `visited_cells` 去重，无限平面里key就是未取模的cell坐标
+                    if self.is_wrapped() || (*cell_pos - center).abs().max_element() >= ring {
+                        self.visit_nearest_cell(*cell_pos, pos, &mut search);
+                    }
+                }
+                break;
+            }
+            self.for_each_ring_cell(center, ring, |cell_pos| {
+                self.visit_nearest_cell(self.wrap_cell(cell_pos), pos, &mut search);
+            });
+
+            // 已访问区域是 [center - ring, center + ring] 的正方形（3D是立方体），
+            // 区域外的实体到 pos 的距离至少是 pos 到这个区域边界的距离
+            let origin = self.cell_origin();
+            let (visited_min, visited_max) = V::ring_bounds(center, ring, self.cell_size);
+            let (visited_min, visited_max) = (origin + visited_min, origin + visited_max);
+            let outside = (pos - visited_min).min(visited_max - pos).min_element();
+            let outside2 = outside * outside;
+            if outside2 > search.max_r2 || search.seen >= self.entity_count {
+                break;
+            }
+            if search.best.len() == k && search.best.peek().unwrap().dist2 <= outside2 {
+                break;
+            }
+            ring += 1;
+        }
+        search
+            .best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|c| c.id)
+            .collect()
+    }
+
+    /// 把map里key为 `cell_pos` 的cell中的实体加入候选
+    fn visit_nearest_cell(&self, cell_pos: V::Cell, pos: V, search: &mut NearestSearch<V::Cell>) {
+        if let Some(visited) = search.visited_cells.as_mut() {
+            if !visited.insert(cell_pos) {
+                return;
+            }
+        }
+        if let Some(ids) = self.extent_map.get(&cell_pos) {
+            for id in ids {
+                if !search.seen_extents.insert(*id) {
+                    continue;
+                }
+                search.seen += 1;
+                let extent = self.extents[*id as usize].as_ref().unwrap();
+                search.push(Candidate {
+                    dist2: self.extent_distance_squared(extent, pos),
+                    id: *id,
+                });
+            }
+        }
+        let Some(grid) = self.map.get(&cell_pos) else {
+            return;
+        };
+        search.seen += grid.payloads.len();
+        for (id, p) in grid.ids().zip(grid.positions.iter()) {
+            search.push(Candidate {
+                dist2: self.displacement(pos, *p).length_squared(),
+                id,
+            });
+        }
+    }
+
+    /// 距离 `pos` 最近的实体
+    pub fn nearest(&self, pos: V) -> Option<u32> {
+        self.nearest_k(pos, 1, f32::INFINITY).first().copied()
+    }
+
+    /// 访问和 `center` 切比雪夫距离正好为 `ring` 的所有cell
+    fn for_each_ring_cell(&self, center: V::Cell, ring: i32, f: impl FnMut(V::Cell)) {
+        V::for_each_ring_cell(center, ring, f);
+    }
+}
+
+#[test]
+fn nearest_k_matches_brute_force() {
+    use glam::Vec2;
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+    let points: Vec<Vec2> = (0..500)
+        .map(|_| Vec2::new(rng.gen_range(-300.0..300.0), rng.gen_range(-300.0..300.0)))
+        .collect();
+    for wrap in [false, true] {
+        let mut map = super::Collision::new(Vec2::new(30., 30.));
+        if wrap {
+            map.with_wrap(Vec2::splat(-300.), Vec2::splat(300.));
+        }
+        for (i, p) in points.iter().enumerate() {
+            map.insert(i as u32, *p);
+        }
+        for _ in 0..50 {
+            let pos = Vec2::new(rng.gen_range(-400.0..400.0), rng.gen_range(-400.0..400.0));
+            for (k, max_radius) in [(1, f32::INFINITY), (7, f32::INFINITY), (7, 60.), (40, 200.)] {
+                let mut expected: Vec<(f32, u32)> = points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| (map.displacement(pos, *p).length_squared(), i as u32))
+                    .filter(|(d, _)| *d <= max_radius * max_radius)
+                    .collect();
+                expected.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                let expected: Vec<u32> = expected.into_iter().take(k).map(|(_, i)| i).collect();
+                assert_eq!(map.nearest_k(pos, k, max_radius), expected);
+            }
+            assert_eq!(
+                map.nearest(pos),
+                map.nearest_k(pos, 1, f32::INFINITY).first().copied()
+            );
+        }
+    }
+}
+
+#[test]
+fn nearest_on_empty_map() {
+    use glam::Vec2;
+    let map = super::Collision::new(Vec2::new(30., 30.));
+    assert_eq!(map.nearest(Vec2::ZERO), None);
+}
+
+#[test]
+fn nearest_far_away_entity_skips_empty_rings() {
+    use glam::Vec2;
+    let mut map = super::Collision::new(Vec2::new(1., 1.));
+    map.insert(0, Vec2::new(1.5, 0.5));
+    map.insert(1, Vec2::new(100_000.5, -70_000.5));
+    map.insert_circle(2, Vec2::new(-90_000., 5.), 2.);
+    // 一圈圈地扫到这么远要查上百亿个空cell，这里应该马上返回
+    assert_eq!(map.nearest(Vec2::new(99_990., -69_990.)), Some(1));
+    assert_eq!(
+        map.nearest_k(Vec2::new(-89_000., 0.), 2, f32::INFINITY),
+        vec![2, 0]
+    );
+    assert_eq!(map.nearest_k(Vec2::new(-89_000., 0.), 3, 50_000.), vec![2]);
+    assert_eq!(
+        map.nearest_k(Vec2::new(0.5, 0.5), 3, f32::INFINITY),
+        vec![0, 2, 1]
+    );
+}
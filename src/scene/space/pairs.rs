@@ -0,0 +1,117 @@
+use std::hash::BuildHasher;
+
+use super::{dim::SpaceCell, unit::IndexGrid, Payload, SpaceMap, SpaceVector};
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 所有距离不超过 `dist` 的无序实体对 `(a, b, 距离)`，每对只出现一次且 `a < b`
+    /// 每个有实体的cell只和自己以及半个邻域比较，环面世界按最近镜像计算距离
+    /// 只包含点实体，有大小的实体用 `query_radius` 单独处理
+    pub fn pairs_within(&self, dist: f32) -> Vec<(u32, u32, f32)> {
+        let mut result = Vec::new();
+        let dist2 = dist * dist;
+        // 距离不超过 dist 的两个点在每个方向上最多相隔这么多个cell
+        let reach = (V::splat(dist) / self.cell_size)
+            .ceil()
+            .as_cell()
+            .max(V::Cell::ZERO);
+        for (cell_pos, grid) in self.map.iter() {
+            let count = grid.payloads.len();
+            for i in 0..count {
+                for j in (i + 1)..count {
+                    self.push_pair(grid, i, grid, j, dist2, &mut result);
+                }
+            }
+            self.for_each_half_neighbor(*cell_pos, reach, |neighbor_pos| {
+                let Some(other) = self.map.get(&neighbor_pos) else {
+                    return;
+                };
+                for i in 0..count {
+                    for j in 0..other.payloads.len() {
+                        self.push_pair(grid, i, other, j, dist2, &mut result);
+                    }
+                }
+            });
+        }
+        result
+    }
+
+    fn push_pair(
+        &self,
+        a: &IndexGrid<V, P>,
+        i: usize,
+        b: &IndexGrid<V, P>,
+        j: usize,
+        dist2: f32,
+        result: &mut Vec<(u32, u32, f32)>,
+    ) {
+        let d2 = self
+            .displacement(a.positions[i], b.positions[j])
+            .length_squared();
+        if d2 <= dist2 {
+            let (id_a, id_b) = (a.payloads[i].id(), b.payloads[j].id());
+            result.push((id_a.min(id_b), id_a.max(id_b), d2.sqrt()));
+        }
+    }
+
+    /// 访问 `cell_pos` 周围 `reach` 范围内的半个邻域（map里的key）
+    /// 在整个邻域里只取 `sort_key` 比自己大的key，任意两个cell之间只会从其中一个访问到另一个
+    /// 环面世界里邻域可能绕回来和自己重叠，按取模之后的key比较同样成立
+    fn for_each_half_neighbor(
+        &self,
+        cell_pos: V::Cell,
+        reach: V::Cell,
+        mut f: impl FnMut(V::Cell),
+    ) {
+        let key = cell_pos.sort_key();
+        self.for_each_cell(cell_pos - reach, cell_pos + reach, |_, neighbor_pos| {
+            if neighbor_pos.sort_key() > key {
+                f(neighbor_pos);
+            }
+        });
+    }
+}
+
+#[test]
+fn pairs_within_match_brute_force() {
+    use glam::Vec2;
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(16);
+    let min = Vec2::new(-10., -10.);
+    let max = Vec2::new(290., 190.);
+    let size = max - min;
+    for wrap in [false, true] {
+        let mut map = super::Collision::new(Vec2::new(30., 30.));
+        if wrap {
+            map.with_wrap(min, max);
+        }
+        let positions: Vec<Vec2> = (0..400)
+            .map(|_| Vec2::new(rng.gen_range(-10.0..290.0), rng.gen_range(-10.0..190.0)))
+            .collect();
+        for (i, p) in positions.iter().enumerate() {
+            map.insert(i as u32, *p);
+        }
+        // 比cell小、和cell一样大、跨好几个cell
+        for dist in [7., 30., 75.] {
+            let mut expected = Vec::new();
+            for a in 0..positions.len() {
+                for b in (a + 1)..positions.len() {
+                    let mut d = positions[b] - positions[a];
+                    if wrap {
+                        d -= (d / size).round() * size;
+                    }
+                    if d.length() <= dist {
+                        expected.push((a as u32, b as u32));
+                    }
+                }
+            }
+            let pairs = map.pairs_within(dist);
+            for (a, b, d) in pairs.iter() {
+                let expected_d = map.displacement(positions[*a as usize], positions[*b as usize]);
+                assert!((expected_d.length() - d).abs() < 1e-3);
+            }
+            let mut got: Vec<(u32, u32)> = pairs.into_iter().map(|(a, b, _)| (a, b)).collect();
+            got.sort();
+            assert_eq!(got, expected);
+        }
+    }
+}
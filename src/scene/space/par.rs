@@ -0,0 +1,100 @@
+use std::hash::BuildHasher;
+
+use rayon::prelude::*;
+
+use super::{
+    dim::SpaceCell,
+    unit::{EntitySlot, IndexGrid},
+    IndexPayload, SpaceMap, SpaceVector,
+};
+
+impl<T, S: BuildHasher + Clone, P: IndexPayload<V>, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 用全部实体的位置并行重建，`positions[i]` 的id为 `i`
+    /// payload用 `from_id` 创建，附带的数据之后再写入
+    /// 先并行算出每个实体的cell再按 (cell, id) 排序，每个cell里的顺序和逐个 `insert` 完全一样
+    pub fn par_build(&mut self, positions: &[V]) {
+        self.clear();
+        let (cell_size, origin, wrap) = (self.cell_size, self.cell_origin(), self.wrap);
+        // 排序缓冲跨帧复用，容量够时不再分配
+        let mut keyed = std::mem::take(&mut self.keyed);
+        keyed.clear();
+        keyed.par_extend(positions.par_iter().enumerate().map(|(i, position)| {
+            let position = wrap.map_or(*position, |wrap| wrap.wrap_position(*position));
+            let cell_pos = (position - origin).cell(cell_size);
+            let cell_pos = wrap.map_or(cell_pos, |wrap| wrap.wrap_cell(cell_pos));
+            (cell_pos, i as u32, position)
+        }));
+        keyed.par_sort_unstable_by_key(|(cell_pos, id, _)| (cell_pos.sort_key(), *id));
+
+        self.entity_slots.resize(positions.len(), None);
+        for run in keyed.chunk_by(|a, b| a.0 == b.0) {
+            let cell_pos = run[0].0;
+            let grid = self.map.entry(cell_pos).or_insert(IndexGrid::new());
+            grid.payloads.reserve(run.len());
+            grid.positions.reserve(run.len());
+            for (_, id, position) in run {
+                grid.insert(P::from_id(*id), *position);
+                self.entity_slots[*id as usize] = Some(EntitySlot {
+                    cell: cell_pos,
+                    index: grid.payloads.len() - 1,
+                });
+            }
+        }
+        if self.border_layer_map.is_some() {
+            for (cell_pos, id, position) in keyed.iter() {
+                self.insert_border(*id, *cell_pos, *position);
+            }
+        }
+        self.keyed = keyed;
+        self.entity_count = positions.len();
+    }
+}
+
+#[test]
+fn par_build_matches_serial_insert() {
+    use glam::Vec2;
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(14);
+    let positions: Vec<Vec2> = (0..5000)
+        .map(|_| Vec2::new(rng.gen_range(-50.0..850.0), rng.gen_range(-50.0..650.0)))
+        .collect();
+    for wrap in [false, true] {
+        let mut serial = super::Collision::new(Vec2::new(40., 40.));
+        let mut parallel = super::Collision::new(Vec2::new(40., 40.));
+        for map in [&mut serial, &mut parallel] {
+            map.with_border_layer(5., 20.);
+            if wrap {
+                map.with_wrap(Vec2::splat(-30.), Vec2::new(830., 630.));
+            }
+        }
+        for (i, p) in positions.iter().enumerate() {
+            serial.insert(i as u32, *p);
+        }
+        parallel.par_build(&positions);
+
+        assert_eq!(serial.entity_count, parallel.entity_count);
+        assert_eq!(serial.map.len(), parallel.map.len());
+        for (cell_pos, grid) in serial.map.iter() {
+            let other = &parallel.map[cell_pos];
+            assert_eq!(grid.payloads, other.payloads);
+            assert_eq!(grid.positions, other.positions);
+        }
+        let serial_border = serial.border_layer_map.as_ref().unwrap();
+        let parallel_border = parallel.border_layer_map.as_ref().unwrap();
+        assert_eq!(serial_border.len(), parallel_border.len());
+        for (key, grid) in serial_border.iter() {
+            assert_eq!(grid.payloads, parallel_border[key].payloads);
+        }
+        for _ in 0..20 {
+            let pos = Vec2::new(rng.gen_range(0.0..800.0), rng.gen_range(0.0..600.0));
+            assert_eq!(
+                serial.query_radius(pos, 25.),
+                parallel.query_radius(pos, 25.)
+            );
+            assert_eq!(
+                serial.nearest_k(pos, 5, 100.),
+                parallel.nearest_k(pos, 5, 100.)
+            );
+        }
+    }
+}
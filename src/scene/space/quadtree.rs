@@ -0,0 +1,467 @@
+use std::{cmp::Ordering, collections::BinaryHeap, marker::PhantomData};
+
+use glam::Vec2;
+
+use super::SpatialIndex;
+
+const NO_CHILDREN: [u32; 4] = [u32::MAX; 4];
+
+struct Node {
+    center: Vec2,
+    half: Vec2,
+    depth: u32,
+    children: [u32; 4],
+    // (id, 圆心, 半径)，点实体半径为0
+    items: Vec<(u32, Vec2, f32)>,
+}
+
+impl Node {
+    fn new(center: Vec2, half: Vec2, depth: u32) -> Self {
+        Node {
+            center,
+            half,
+            depth,
+            children: NO_CHILDREN,
+            items: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children == NO_CHILDREN
+    }
+
+    /// 点所在的象限，和 `child_center` 的顺序一致
+    fn quadrant(&self, pos: Vec2) -> usize {
+        (pos.x >= self.center.x) as usize | (((pos.y >= self.center.y) as usize) << 1)
+    }
+
+    fn child_center(&self, quadrant: usize) -> Vec2 {
+        let sign = Vec2::new(
+            if quadrant & 1 == 1 { 1. } else { -1. },
+            if quadrant & 2 == 2 { 1. } else { -1. },
+        );
+        self.center + sign * self.half / 2.
+    }
+
+    /// `pos` 到节点紧包围盒的距离平方，在盒内为0
+    fn dist2_to(&self, pos: Vec2, expand: f32) -> f32 {
+        let half = self.half + Vec2::splat(expand);
+        let d = ((pos - self.center).abs() - half).max(Vec2::ZERO);
+        d.length_squared()
+    }
+}
+
+/// 松散四叉树，实体扎堆时自适应细分，避免均匀网格退化成 O(n²)
+/// 实体放在圆心所在、且松散边界能完全包住它的最深节点上；
+/// 叶子超过 `bucket_capacity` 个实体时分裂，`max_depth` 防止重合的点无限细分
+pub struct LooseQuadtree<T> {
+    nodes: Vec<Node>,
+    root: u32,
+    bucket_capacity: usize,
+    max_depth: u32,
+    // 松散系数，节点的松散边界是紧边界的这么多倍
+    looseness: f32,
+    // 所有实体里最大的半径，范围查询时用来放宽剪枝
+    max_radius: f32,
+    initial_center: Vec2,
+    initial_half: Vec2,
+    // 只用来区分层，不影响 Send/Sync
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> LooseQuadtree<T> {
+    pub fn new(min: Vec2, max: Vec2) -> Self {
+        Self::with_params(min, max, 8, 12, 2.)
+    }
+
+    pub fn with_params(
+        min: Vec2,
+        max: Vec2,
+        bucket_capacity: usize,
+        max_depth: u32,
+        looseness: f32,
+    ) -> Self {
+        let initial_center = (min + max) / 2.;
+        let initial_half = ((max - min) / 2.).max(Vec2::splat(f32::EPSILON));
+        LooseQuadtree {
+            nodes: vec![Node::new(initial_center, initial_half, 0)],
+            root: 0,
+            bucket_capacity: bucket_capacity.max(1),
+            max_depth,
+            looseness: looseness.max(1.),
+            max_radius: 0.,
+            initial_center,
+            initial_half,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.nodes
+            .push(Node::new(self.initial_center, self.initial_half, 0));
+        self.root = 0;
+        self.max_radius = 0.;
+    }
+
+    pub fn insert(&mut self, entity_id: u32, position: Vec2) {
+        self.insert_circle(entity_id, position, 0.);
+    }
+
+    /// 插入带半径的实体
+    pub fn insert_circle(&mut self, entity_id: u32, center: Vec2, radius: f32) {
+        self.grow_to_contain(center);
+        self.max_radius = self.max_radius.max(radius);
+        let mut index = self.root as usize;
+        loop {
+            let node = &self.nodes[index];
+            if node.is_leaf() {
+                break;
+            }
+            let child = node.children[node.quadrant(center)] as usize;
+            if !self.fits(child, radius) {
+                break;
+            }
+            index = child;
+        }
+        self.nodes[index].items.push((entity_id, center, radius));
+        if self.nodes[index].is_leaf()
+            && self.nodes[index].items.len() > self.bucket_capacity
+            && self.nodes[index].depth < self.max_depth
+        {
+            self.split(index);
+        }
+    }
+
+    /// 移除实体，`position` 是插入时的圆心
+    pub fn remove(&mut self, entity_id: u32, position: Vec2) -> bool {
+        let mut index = self.root as usize;
+        loop {
+            let node = &mut self.nodes[index];
+            if let Some(i) = node.items.iter().position(|(id, _, _)| *id == entity_id) {
+                node.items.swap_remove(i);
+                return true;
+            }
+            if node.is_leaf() {
+                return false;
+            }
+            index = node.children[node.quadrant(position)] as usize;
+        }
+    }
+
+    /// 查询圆形范围内的实体，带半径的实体只要和查询圆相交就算
+    pub fn query_radius(&self, pos: Vec2, r: f32) -> Vec<u32> {
+        let mut result = Vec::new();
+        // 实体的圆心在节点紧边界内，整个圆在松散边界内
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            // 根节点上可能挂着装不进任何子节点的大实体
+            let expand = if index == self.root {
+                self.max_radius
+            } else {
+                (node.half.min_element() * (self.looseness - 1.)).min(self.max_radius)
+            };
+            if node.dist2_to(pos, expand) > r * r {
+                continue;
+            }
+            for (id, center, radius) in node.items.iter() {
+                let reach = r + radius;
+                if center.distance_squared(pos) <= reach * reach {
+                    result.push(*id);
+                }
+            }
+            if !node.is_leaf() {
+                stack.extend(node.children);
+            }
+        }
+        result
+    }
+
+    /// 查询圆心在轴对齐矩形 `[min, max]`（含边界）内的实体
+    pub fn query_aabb(&self, min: Vec2, max: Vec2) -> Vec<u32> {
+        let mut result = Vec::new();
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            let node_min = node.center - node.half;
+            let node_max = node.center + node.half;
+            if node_min.cmpgt(max).any() || node_max.cmplt(min).any() {
+                continue;
+            }
+            for (id, center, _) in node.items.iter() {
+                if center.cmpge(min).all() && center.cmple(max).all() {
+                    result.push(*id);
+                }
+            }
+            if !node.is_leaf() {
+                stack.extend(node.children);
+            }
+        }
+        result
+    }
+
+    /// 圆心距离 `pos` 最近的实体，距离相同时取id小的
+    /// 按节点到 `pos` 的距离做最优优先搜索
+    pub fn nearest(&self, pos: Vec2) -> Option<u32> {
+        let mut best: Option<(f32, u32)> = None;
+        let mut heap = BinaryHeap::new();
+        heap.push(NodeDist {
+            dist2: self.nodes[self.root as usize].dist2_to(pos, 0.),
+            index: self.root,
+        });
+        while let Some(NodeDist { dist2, index }) = heap.pop() {
+            if best.is_some_and(|b| b.0 < dist2) {
+                break;
+            }
+            let node = &self.nodes[index as usize];
+            for (id, center, _) in node.items.iter() {
+                let candidate = (center.distance_squared(pos), *id);
+                let closer = match best {
+                    Some(b) => candidate.0 < b.0 || (candidate.0 == b.0 && candidate.1 < b.1),
+                    None => true,
+                };
+                if closer {
+                    best = Some(candidate);
+                }
+            }
+            if !node.is_leaf() {
+                for child in node.children {
+                    heap.push(NodeDist {
+                        dist2: self.nodes[child as usize].dist2_to(pos, 0.),
+                        index: child,
+                    });
+                }
+            }
+        }
+        best.map(|b| b.1)
+    }
+
+    /// 实体能不能放进这个节点：松散边界比紧边界多出来的部分要装得下半径
+    fn fits(&self, index: usize, radius: f32) -> bool {
+        radius <= self.nodes[index].half.min_element() * (self.looseness - 1.)
+    }
+
+    fn split(&mut self, index: usize) {
+        let (half, depth) = (self.nodes[index].half, self.nodes[index].depth);
+        let mut children = NO_CHILDREN;
+        for (quadrant, child) in children.iter_mut().enumerate() {
+            *child = self.nodes.len() as u32;
+            let child_center = self.nodes[index].child_center(quadrant);
+            self.nodes
+                .push(Node::new(child_center, half / 2., depth + 1));
+        }
+        self.nodes[index].children = children;
+        let items = std::mem::take(&mut self.nodes[index].items);
+        for item in items {
+            let child = children[self.nodes[index].quadrant(item.1)] as usize;
+            if self.fits(child, item.2) {
+                self.nodes[child].items.push(item);
+            } else {
+                self.nodes[index].items.push(item);
+            }
+        }
+        // 重合的点可能全部挤进同一个子节点，继续往下分
+        for child in children {
+            let child = child as usize;
+            if self.nodes[child].items.len() > self.bucket_capacity
+                && self.nodes[child].depth < self.max_depth
+            {
+                self.split(child);
+            }
+        }
+    }
+
+    /// 点落在根节点外面时把根节点往那个方向扩大一倍，旧根变成新根的一个子节点
+    /// 旧根上松散边界装不下的大实体从新根重新插入，不然按节点边界剪枝时会漏掉
+    fn grow_to_contain(&mut self, pos: Vec2) {
+        let old_root = self.root as usize;
+        loop {
+            let root = &self.nodes[self.root as usize];
+            let (root_center, root_half) = (root.center, root.half);
+            let offset = pos - root_center;
+            if offset.x.abs() <= root_half.x && offset.y.abs() <= root_half.y {
+                break;
+            }
+            let sign = Vec2::new(offset.x.signum(), offset.y.signum());
+            let mut new_root = Node::new(root_center + sign * root_half, root_half * 2., 0);
+            let old_quadrant = new_root.quadrant(root_center);
+            for quadrant in 0..4 {
+                if quadrant == old_quadrant {
+                    new_root.children[quadrant] = self.root;
+                } else {
+                    new_root.children[quadrant] = self.nodes.len() as u32;
+                    let child_center = new_root.child_center(quadrant);
+                    self.nodes.push(Node::new(child_center, root_half, 1));
+                }
+            }
+            self.bump_depth(self.root as usize);
+            self.root = self.nodes.len() as u32;
+            self.nodes.push(new_root);
+        }
+        if self.root as usize == old_root {
+            return;
+        }
+        let items = std::mem::take(&mut self.nodes[old_root].items);
+        let (kept, moved): (Vec<_>, Vec<_>) = items
+            .into_iter()
+            .partition(|item| self.fits(old_root, item.2));
+        self.nodes[old_root].items = kept;
+        for (id, center, radius) in moved {
+            self.insert_circle(id, center, radius);
+        }
+    }
+
+    /// 旧根整体下沉一层后更新深度
+    fn bump_depth(&mut self, index: usize) {
+        let mut stack = vec![index];
+        while let Some(index) = stack.pop() {
+            self.nodes[index].depth += 1;
+            if !self.nodes[index].is_leaf() {
+                stack.extend(self.nodes[index].children.map(|c| c as usize));
+            }
+        }
+    }
+}
+
+/// 最优优先搜索里的节点，距离小的先出堆
+struct NodeDist {
+    dist2: f32,
+    index: u32,
+}
+
+impl PartialEq for NodeDist {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for NodeDist {}
+impl PartialOrd for NodeDist {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for NodeDist {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .dist2
+            .total_cmp(&self.dist2)
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+impl<T> SpatialIndex for LooseQuadtree<T> {
+    fn clear(&mut self) {
+        LooseQuadtree::clear(self);
+    }
+    fn insert(&mut self, entity_id: u32, position: Vec2) {
+        LooseQuadtree::insert(self, entity_id, position);
+    }
+    fn remove(&mut self, entity_id: u32, position: Vec2) -> bool {
+        LooseQuadtree::remove(self, entity_id, position)
+    }
+    fn query_radius(&self, pos: Vec2, r: f32) -> Vec<u32> {
+        LooseQuadtree::query_radius(self, pos, r)
+    }
+    fn query_aabb(&self, min: Vec2, max: Vec2) -> Vec<u32> {
+        LooseQuadtree::query_aabb(self, min, max)
+    }
+    fn nearest(&self, pos: Vec2) -> Option<u32> {
+        LooseQuadtree::nearest(self, pos)
+    }
+}
+
+#[test]
+fn quadtree_matches_space_map() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+    let mut tree = LooseQuadtree::<super::CollisionMarker>::new(Vec2::ZERO, Vec2::new(800., 600.));
+    let mut map = super::Collision::new(Vec2::new(50., 50.));
+    // 一半实体挤在目标点附近，一半散开，还有一些在初始范围外
+    let mut positions: Vec<Vec2> = (0..1500)
+        .map(|i| {
+            if i % 2 == 0 {
+                Vec2::new(400., 400.)
+                    + Vec2::new(rng.gen_range(-3.0..3.0), rng.gen_range(-3.0..3.0))
+            } else {
+                Vec2::new(rng.gen_range(-300.0..1100.0), rng.gen_range(-300.0..900.0))
+            }
+        })
+        .collect();
+    positions.push(Vec2::new(400., 400.));
+    positions.push(Vec2::new(400., 400.));
+    SpatialIndex::build(&mut tree, &positions);
+    SpatialIndex::build(&mut map, &positions);
+    let sorted = |mut ids: Vec<u32>| {
+        ids.sort();
+        ids
+    };
+    for i in 0..100 {
+        let pos = if i % 3 == 0 {
+            Vec2::new(400., 400.) + Vec2::new(rng.gen_range(-5.0..5.0), rng.gen_range(-5.0..5.0))
+        } else {
+            Vec2::new(rng.gen_range(-400.0..1200.0), rng.gen_range(-400.0..1000.0))
+        };
+        let r = rng.gen_range(0.5..150.0);
+        assert_eq!(
+            sorted(tree.query_radius(pos, r)),
+            sorted(map.query_radius(pos, r))
+        );
+        let max = pos + Vec2::new(rng.gen_range(0.0..300.0), rng.gen_range(0.0..300.0));
+        assert_eq!(
+            sorted(tree.query_aabb(pos, max)),
+            sorted(map.query_aabb(pos, max))
+        );
+        assert_eq!(tree.nearest(pos), map.nearest(pos));
+    }
+    for i in (0..positions.len()).step_by(7) {
+        assert!(tree.remove(i as u32, positions[i]));
+        assert!(map.remove(i as u32));
+    }
+    assert!(!tree.remove(0, positions[0]));
+    let pos = Vec2::new(401., 399.);
+    assert_eq!(
+        sorted(tree.query_radius(pos, 20.)),
+        sorted(map.query_radius(pos, 20.))
+    );
+}
+
+#[test]
+fn quadtree_circles_found_from_neighbouring_nodes() {
+    let mut tree = LooseQuadtree::<super::CollisionMarker>::with_params(
+        Vec2::ZERO,
+        Vec2::splat(100.),
+        1,
+        8,
+        2.,
+    );
+    tree.insert_circle(0, Vec2::new(45., 45.), 10.);
+    for i in 1..20 {
+        tree.insert(i, Vec2::new(i as f32 * 5., 90.));
+    }
+    // 查询圆只碰到实体0的边缘，圆心在另一个象限里
+    assert_eq!(tree.query_radius(Vec2::new(58., 45.), 4.), vec![0]);
+    assert!(tree.query_radius(Vec2::new(58., 45.), 2.).is_empty());
+}
+
+#[test]
+fn quadtree_keeps_big_circles_visible_after_growing() {
+    let mut tree = LooseQuadtree::<super::CollisionMarker>::with_params(
+        Vec2::ZERO,
+        Vec2::splat(100.),
+        1,
+        8,
+        2.,
+    );
+    // 半径超过根节点的松散边界，只能挂在根上
+    tree.insert_circle(0, Vec2::new(50., 50.), 120.);
+    tree.insert(1, Vec2::new(20., 20.));
+    // 往外插入让根节点扩大几次，旧根变成深处的节点
+    tree.insert(2, Vec2::new(500., 50.));
+    assert_eq!(tree.query_radius(Vec2::new(50., 175.), 10.), vec![0]);
+    assert_eq!(tree.query_radius(Vec2::new(175., 50.), 10.), vec![0]);
+    assert!(tree.query_radius(Vec2::new(50., 185.), 4.).is_empty());
+    assert!(tree.remove(0, Vec2::new(50., 50.)));
+    assert!(tree.query_radius(Vec2::new(50., 175.), 10.).is_empty());
+    assert_eq!(tree.query_radius(Vec2::new(20., 20.), 1.), vec![1]);
+}
@@ -0,0 +1,303 @@
+use std::{collections::HashSet, hash::BuildHasher};
+
+use glam::{IVec2, Vec2};
+
+use super::{unit::IndexGrid, Payload, SpaceMap};
+use crate::scene::entity::instance::_CircleInstance;
+
+/// 射线命中的实体和沿射线的距离
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub id: u32,
+    pub distance: f32,
+}
+
+/// Amanatides–Woo 网格遍历，按顺序给出线段经过的cell以及进入该cell时的参数 t（0..=1）
+struct CellWalk {
+    cell: IVec2,
+    end_cell: IVec2,
+    step: IVec2,
+    t_max: Vec2,
+    t_delta: Vec2,
+    t_enter: f32,
+    steps_left: i32,
+    done: bool,
+}
+
+impl CellWalk {
+    fn new(start_cell: IVec2, end_cell: IVec2, start: Vec2, end: Vec2, cell_size: Vec2) -> Self {
+        let d = end - start;
+        let step = IVec2::new(d.x.signum() as i32, d.y.signum() as i32);
+        let axis = |d: f32, start: f32, cell: i32, cell_size: f32| {
+            if d == 0. {
+                return (f32::INFINITY, f32::INFINITY);
+            }
+            let boundary = if d > 0. { cell + 1 } else { cell } as f32 * cell_size;
+            ((boundary - start) / d, cell_size / d.abs())
+        };
+        let (t_max_x, t_delta_x) = axis(d.x, start.x, start_cell.x, cell_size.x);
+        let (t_max_y, t_delta_y) = axis(d.y, start.y, start_cell.y, cell_size.y);
+        let diff = (end_cell - start_cell).abs();
+        CellWalk {
+            cell: start_cell,
+            end_cell,
+            step,
+            t_max: Vec2::new(t_max_x, t_max_y),
+            t_delta: Vec2::new(t_delta_x, t_delta_y),
+            t_enter: 0.,
+            // 浮点误差兜底，线段最多跨过这么多条cell边界
+            steps_left: diff.x + diff.y,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for CellWalk {
+    type Item = (IVec2, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let current = (self.cell, self.t_enter);
+        if self.cell == self.end_cell || self.steps_left <= 0 {
+            self.done = true;
+            return Some(current);
+        }
+        if self.t_max.x < self.t_max.y {
+            self.cell.x += self.step.x;
+            self.t_enter = self.t_max.x;
+            self.t_max.x += self.t_delta.x;
+        } else {
+            self.cell.y += self.step.y;
+            self.t_enter = self.t_max.y;
+            self.t_max.y += self.t_delta.y;
+        }
+        self.steps_left -= 1;
+        if self.t_enter > 1. {
+            self.done = true;
+        }
+        Some(current)
+    }
+}
+
+/// 线段经过的cell，按顺序给出cell坐标和该cell的 `IndexGrid`（空cell为 `None`）
+pub struct CellTraversal<'a, T, S, P = u32> {
+    space: &'a SpaceMap<T, S, P>,
+    walk: CellWalk,
+}
+
+impl<'a, T, S: BuildHasher + Clone, P: Payload> Iterator for CellTraversal<'a, T, S, P> {
+    type Item = (IVec2, Option<&'a IndexGrid<Vec2, P>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (cell, _) = self.walk.next()?;
+        let cell = self.space.wrap_cell(cell);
+        Some((cell, self.space.map.get(&cell)))
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload> SpaceMap<T, S, P> {
+    fn cell_walk(&self, start: Vec2, end: Vec2) -> CellWalk {
+        // 按未取模的cell走，查map时再取模
+        let origin = self.cell_origin();
+        CellWalk::new(
+            self.raw_cell_index(start),
+            self.raw_cell_index(end),
+            start - origin,
+            end - origin,
+            self.cell_size,
+        )
+    }
+
+    /// 按顺序遍历线段 `start -> end` 经过的所有cell
+    pub fn traverse_segment(&self, start: Vec2, end: Vec2) -> CellTraversal<'_, T, S, P> {
+        CellTraversal {
+            space: self,
+            walk: self.cell_walk(start, end),
+        }
+    }
+
+    /// 从 `origin` 沿 `dir` 发射长度为 `max_distance` 的射线，返回最先命中的圆
+    /// `instances` 按实体id索引，用其中的 `radius` 做圆相交测试，不在 `instances` 里的id会被跳过
+    /// 圆心可能落在射线没经过的cell里，所以每个cell会按最大半径向外多查几圈
+    /// 只检测点实体，`insert_circle` / `insert_aabb` 加入的有大小实体不会被命中
+    pub fn raycast_circles(
+        &self,
+        origin: Vec2,
+        dir: Vec2,
+        max_distance: f32,
+        instances: &[_CircleInstance],
+    ) -> Option<RayHit> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec2::ZERO || max_distance <= 0. {
+            return None;
+        }
+        let max_radius = instances.iter().map(|i| i.radius).fold(0., f32::max);
+        let pad = (Vec2::splat(max_radius) / self.cell_size).ceil().as_ivec2();
+        let end = origin + dir * max_distance;
+
+        let mut best: Option<RayHit> = None;
+        let mut tested = HashSet::new();
+        for (cell, t_enter) in self.cell_walk(origin, end) {
+            // 后面cell里的命中点距离至少是进入该cell的距离
+            if best.is_some_and(|hit| hit.distance <= t_enter * max_distance) {
+                break;
+            }
+            for y in -pad.y..=pad.y {
+                for x in -pad.x..=pad.x {
+                    let raw_cell = cell + IVec2::new(x, y);
+                    let near_cell = self.wrap_cell(raw_cell);
+                    if !tested.insert(near_cell) {
+                        continue;
+                    }
+                    let Some(grid) = self.map.get(&near_cell) else {
+                        continue;
+                    };
+                    // 环面世界里取圆心落在这个未取模cell里的那个镜像
+                    let cell_center = self.get_cell_center(&raw_cell);
+                    for id in grid.ids() {
+                        let Some(instance) = instances.get(id as usize) else {
+                            continue;
+                        };
+                        let center = Vec2::from_array(instance.position);
+                        let center = cell_center + self.displacement(cell_center, center);
+                        let Some(distance) = ray_circle(origin, dir, center, instance.radius)
+                        else {
+                            continue;
+                        };
+                        if distance > max_distance {
+                            continue;
+                        }
+                        // 距离相同取id小的，保证结果和遍历顺序无关
+                        let closer = match best {
+                            Some(hit) => {
+                                distance < hit.distance || (distance == hit.distance && id < hit.id)
+                            }
+                            None => true,
+                        };
+                        if closer {
+                            best = Some(RayHit { id, distance });
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+/// 射线和圆的最近交点距离，起点在圆内时为0
+fn ray_circle(origin: Vec2, dir: Vec2, center: Vec2, radius: f32) -> Option<f32> {
+    let m = origin - center;
+    let c = m.length_squared() - radius * radius;
+    if c <= 0. {
+        return Some(0.);
+    }
+    let b = m.dot(dir);
+    if b > 0. {
+        return None;
+    }
+    let disc = b * b - c;
+    if disc < 0. {
+        return None;
+    }
+    Some(-b - disc.sqrt())
+}
+
+#[test]
+fn traverse_segment_visits_adjacent_cells_in_order() {
+    let map = super::Collision::new(Vec2::new(10., 10.));
+    let segments = [
+        (Vec2::new(1., 1.), Vec2::new(95., 33.)),
+        (Vec2::new(-23., 47.), Vec2::new(12., -64.)),
+        (Vec2::new(5., 5.), Vec2::new(5., -45.)),
+        (Vec2::new(5., 5.), Vec2::new(7., 8.)),
+    ];
+    for (start, end) in segments {
+        let cells: Vec<IVec2> = map.traverse_segment(start, end).map(|(c, _)| c).collect();
+        assert_eq!(cells.first(), Some(&map.get_cell_index(start)));
+        assert_eq!(cells.last(), Some(&map.get_cell_index(end)));
+        for pair in cells.windows(2) {
+            let diff = (pair[1] - pair[0]).abs();
+            assert_eq!(diff.x + diff.y, 1);
+        }
+        // 线段上的采样点都落在遍历到的cell里
+        for i in 0..=200 {
+            let p = start.lerp(end, i as f32 / 200.);
+            assert!(cells.contains(&map.get_cell_index(p)));
+        }
+    }
+}
+
+#[test]
+fn raycast_circles_matches_brute_force() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+    let instances: Vec<_CircleInstance> = (0..300)
+        .map(|_| _CircleInstance {
+            position: [rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0)],
+            velocity: [0., 0.],
+            radius: rng.gen_range(1.0..30.0),
+        })
+        .collect();
+    for wrap in [false, true] {
+        let mut map = super::Collision::new(Vec2::new(20., 20.));
+        if wrap {
+            map.with_wrap(Vec2::splat(-200.), Vec2::splat(200.));
+        }
+        for (i, instance) in instances.iter().enumerate() {
+            map.insert(i as u32, Vec2::from_array(instance.position));
+        }
+        for _ in 0..100 {
+            let origin = Vec2::new(rng.gen_range(-250.0..250.0), rng.gen_range(-250.0..250.0));
+            let dir = Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU));
+            // 射线加最大半径比半个世界短，最近镜像就是可能被命中的那个
+            let expected = instances
+                .iter()
+                .enumerate()
+                .filter_map(|(i, instance)| {
+                    let center = Vec2::from_array(instance.position);
+                    let center = origin + map.displacement(origin, center);
+                    ray_circle(origin, dir, center, instance.radius)
+                        .filter(|d| *d <= 150.)
+                        .map(|distance| (distance, i as u32))
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            let hit = map.raycast_circles(origin, dir, 150., &instances);
+            assert_eq!(hit.map(|h| h.id), expected.map(|e| e.1));
+            if let (Some(hit), Some(expected)) = (hit, expected) {
+                assert!((hit.distance - expected.0).abs() < 1e-3);
+            }
+        }
+    }
+
+    // 接缝另一边的圆
+    let mut map = super::Collision::new(Vec2::new(20., 20.));
+    map.with_wrap(Vec2::ZERO, Vec2::splat(200.));
+    let seam = [_CircleInstance {
+        position: [195., 100.],
+        velocity: [0., 0.],
+        radius: 3.,
+    }];
+    map.insert(0, Vec2::new(195., 100.));
+    let hit = map.raycast_circles(Vec2::new(5., 100.), Vec2::NEG_X, 20., &seam);
+    assert_eq!(hit.map(|h| h.id), Some(0));
+    assert!((hit.unwrap().distance - 7.).abs() < 1e-3);
+}
+
+#[test]
+fn raycast_skips_ids_missing_from_instances() {
+    let mut map = super::Collision::new(Vec2::new(20., 20.));
+    let instances = [_CircleInstance {
+        position: [30., 0.],
+        velocity: [0., 0.],
+        radius: 2.,
+    }];
+    map.insert(0, Vec2::new(30., 0.));
+    // 已经删掉的实体，或者调用方只传了一部分实例
+    map.insert(1, Vec2::new(15., 0.));
+    let hit = map.raycast_circles(Vec2::ZERO, Vec2::X, 100., &instances);
+    assert_eq!(hit.map(|h| h.id), Some(0));
+    assert!((hit.unwrap().distance - 28.).abs() < 1e-3);
+}
@@ -0,0 +1,76 @@
+use std::hash::BuildHasher;
+
+use super::{Payload, SpaceMap, SpaceVector};
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 每帧用 `build` 整体重建，`clear` 只清空cell不释放，cell的容量留给下一帧
+    /// 连续 `evict_after` 次重建都是空的cell才从map里删掉，实体分布稳定后重建不再分配内存
+    pub fn with_bucket_reuse(&mut self, evict_after: u32) {
+        self.evict_after = Some(evict_after);
+    }
+
+    pub fn reuses_buckets(&self) -> bool {
+        self.evict_after.is_some()
+    }
+
+    /// 清空每个cell但保留分配，顺便删掉空太久的cell
+    pub(super) fn truncate_buckets(&mut self, evict_after: u32) {
+        self.map.retain(|_, grid| grid.truncate() < evict_after);
+        if let Some(border_map) = self.border_layer_map.as_mut() {
+            border_map.retain(|_, grid| grid.truncate() < evict_after);
+        }
+        // 有大小的实体很少，上一次重建已经空了的列表直接释放
+        self.extent_map.retain(|_, ids| {
+            let keep = !ids.is_empty();
+            ids.clear();
+            keep
+        });
+    }
+
+    /// 连同cell一起释放
+    pub(super) fn drop_buckets(&mut self) {
+        self.map.clear();
+        if let Some(border_map) = self.border_layer_map.as_mut() {
+            border_map.clear();
+        }
+        self.extent_map.clear();
+    }
+}
+
+#[test]
+fn rebuild_keeps_buckets_and_evicts_idle_cells() {
+    use super::SpatialIndex;
+    use glam::{IVec2, Vec2};
+    let mut map = super::Collision::new(Vec2::new(10., 10.));
+    map.with_border_layer(1., 2.);
+    map.with_bucket_reuse(2);
+    let mut positions = vec![Vec2::new(5., 5.), Vec2::new(9., 5.), Vec2::new(25., 5.)];
+    map.build(&positions);
+    let bucket = |map: &super::Collision, cell: IVec2| {
+        let grid = map.get_index_grid_by_pos(&cell)?;
+        Some((grid.payloads.as_ptr(), grid.payloads.capacity()))
+    };
+    let first = bucket(&map, IVec2::ZERO).unwrap();
+
+    // 实体留在原来的cell，重建不会换掉cell的存储
+    let old = positions.clone();
+    positions[1] = Vec2::new(8., 6.);
+    positions[2] = Vec2::new(45., 5.);
+    map.update_positions(&old, &positions);
+    assert_eq!(bucket(&map, IVec2::ZERO), Some(first));
+    assert_eq!(map.query_radius(Vec2::new(45., 5.), 1.), vec![2]);
+    assert_eq!(map.query_radius(Vec2::new(25., 5.), 1.), Vec::<u32>::new());
+    assert!(map.get_index_grid_by_pos(&IVec2::new(2, 0)).is_some());
+    let mut ids = map.query_radius(Vec2::new(8., 5.), 2.);
+    ids.sort();
+    assert_eq!(ids, vec![1]);
+    assert_eq!(map.entity_count(), 3);
+
+    // 连续空了两次重建的cell被删掉，一直有实体的cell保留
+    map.build(&positions);
+    assert!(map.get_index_grid_by_pos(&IVec2::new(2, 0)).is_some());
+    map.build(&positions);
+    assert!(map.get_index_grid_by_pos(&IVec2::new(2, 0)).is_none());
+    assert_eq!(bucket(&map, IVec2::ZERO), Some(first));
+    assert_eq!(map.query_radius(Vec2::new(5., 5.), 1.), vec![0]);
+}
@@ -0,0 +1,469 @@
+use std::{collections::HashMap, fmt, fs, hash::BuildHasher, io, path::Path};
+
+use bincode::Options;
+use glam::{IVec2, Vec2};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    dim::SpaceCell,
+    extent::Extent,
+    unit::{EntitySlot, IndexGrid},
+    wrap::Wrap,
+    BorderDir, IndexPayload, Payload, Space, SpaceMap, SpaceVector,
+};
+
+/// 一个cell里的实体，`ids[i]` 的位置是 `positions[i]`，顺序和map里一致
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct CellSnapshot<K, V = Vec2> {
+    key: K,
+    ids: Vec<u32>,
+    positions: Vec<V>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct BorderSnapshot {
+    line_width: f32,
+    cells: Vec<CellSnapshot<(IVec2, BorderDir)>>,
+}
+
+/// `SpaceMap` 的完整状态，调试时把出问题的那一帧存下来，在测试里原样加载
+/// 主层和边界层逐个cell保存（包括已经空了的cell），有大小的实体只存形状，加载时重新登记
+/// cell里只存实体id，payload附带的速度加载后是 `from_id` 给的值，下一次 `update_velocities` 再写入
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "V: Serialize, V::Cell: Serialize",
+    deserialize = "V: Deserialize<'de>, V::Cell: Deserialize<'de>"
+))]
+pub struct SpaceMapSnapshot<V: SpaceVector = Vec2> {
+    cell_size: V,
+    cells: Vec<CellSnapshot<V::Cell, V>>,
+    border: Option<BorderSnapshot>,
+    extents: Vec<(u32, Extent<V>)>,
+    // 环面世界的 `[min, max)`
+    wrap: Option<(V, V)>,
+}
+
+/// `Space` 中所有 `SpaceMap` 层的快照，按注册顺序带上层的名字
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpaceSnapshot {
+    levels: Vec<(String, SpaceMapSnapshot)>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    /// 二进制数据开头不是快照的标记，或者版本不认识
+    BadHeader,
+    /// 快照内容自相矛盾，比如同一个id出现在两个cell里
+    Invalid(String),
+    /// 快照里的层没有在 `Space` 中注册
+    UnknownLevel(String),
+    /// 这一层不是 `SpaceMap`，不能从快照恢复
+    Unsupported,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot io error: {}", err),
+            SnapshotError::Json(err) => write!(f, "snapshot json error: {}", err),
+            SnapshotError::Binary(err) => write!(f, "snapshot binary error: {}", err),
+            SnapshotError::BadHeader => write!(f, "not a space snapshot"),
+            SnapshotError::Invalid(reason) => write!(f, "invalid snapshot: {}", reason),
+            SnapshotError::UnknownLevel(name) => write!(f, "unknown space level {}", name),
+            SnapshotError::Unsupported => write!(f, "space level does not support snapshots"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> Self {
+        SnapshotError::Json(err)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(err: bincode::Error) -> Self {
+        SnapshotError::Binary(err)
+    }
+}
+
+/// 二进制快照的开头：标记加格式版本
+const MAGIC: &[u8; 4] = b"SPHS";
+const VERSION: u8 = 1;
+
+/// 快照的两种格式：方便直接看的JSON和紧凑的二进制
+/// JSON存不了NaN和无穷大，位置已经坏掉的帧要用二进制格式保存
+pub trait SnapshotFormat: Serialize + DeserializeOwned {
+    fn to_json(&self) -> Result<String, SnapshotError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    fn from_json(json: &str) -> Result<Self, SnapshotError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// 标记和版本之后是bincode的变长整数编码，小的id和cell坐标只占一两个字节
+    fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bincode::DefaultOptions::new().serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let data = bytes
+            .strip_prefix(MAGIC.as_slice())
+            .and_then(|rest| rest.strip_prefix(&[VERSION]))
+            .ok_or(SnapshotError::BadHeader)?;
+        Ok(bincode::DefaultOptions::new().deserialize(data)?)
+    }
+
+    /// 按扩展名选格式，`.json` 存JSON，其它都存二进制
+    fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        if is_json(path) {
+            fs::write(path, self.to_json()?)?;
+        } else {
+            fs::write(path, self.to_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        if is_json(path) {
+            Self::from_json(&fs::read_to_string(path)?)
+        } else {
+            Self::from_bytes(&fs::read(path)?)
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+impl<V: SpaceVector> SnapshotFormat for SpaceMapSnapshot<V> where Self: Serialize + DeserializeOwned {}
+impl SnapshotFormat for SpaceSnapshot {}
+
+/// 按 `order` 排好cell，同样的map总是得到同样的文件，方便diff
+fn cell_snapshots<K: Copy, V: Copy, P: Payload, S, O: Ord>(
+    map: &HashMap<K, IndexGrid<V, P>, S>,
+    order: impl Fn(&K) -> O,
+) -> Vec<CellSnapshot<K, V>> {
+    let mut cells: Vec<CellSnapshot<K, V>> = map
+        .iter()
+        .map(|(key, grid)| CellSnapshot {
+            key: *key,
+            ids: grid.ids().collect(),
+            positions: grid.positions.clone(),
+        })
+        .collect();
+    cells.sort_by_key(|cell| order(&cell.key));
+    cells
+}
+
+fn index_grid<K, V: Copy, P: IndexPayload<V>>(
+    cell: &CellSnapshot<K, V>,
+) -> Result<IndexGrid<V, P>, SnapshotError> {
+    if cell.ids.len() != cell.positions.len() {
+        return Err(SnapshotError::Invalid(format!(
+            "{} ids but {} positions in one cell",
+            cell.ids.len(),
+            cell.positions.len()
+        )));
+    }
+    Ok(IndexGrid {
+        payloads: cell.ids.iter().map(|id| P::from_id(*id)).collect(),
+        positions: cell.positions.clone(),
+        idle_frames: 0,
+    })
+}
+
+impl<T, S: BuildHasher + Clone + Default, P: IndexPayload<V>, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 从快照创建，测试里加载保存下来的帧用
+    pub fn from_snapshot(snapshot: &SpaceMapSnapshot<V>) -> Result<Self, SnapshotError> {
+        Self::from_snapshot_with_hasher(snapshot, S::default())
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: IndexPayload<V>, V: SpaceVector> SpaceMap<T, S, P, V> {
+    pub fn snapshot(&self) -> SpaceMapSnapshot<V> {
+        let extents = self
+            .extents
+            .iter()
+            .enumerate()
+            .filter_map(|(id, extent)| Some((id as u32, (*extent)?)))
+            .collect();
+        SpaceMapSnapshot {
+            cell_size: self.cell_size,
+            cells: cell_snapshots(&self.map, |key| key.sort_key()),
+            border: self
+                .border_layer_map
+                .as_ref()
+                .map(|border_map| BorderSnapshot {
+                    line_width: self.border_line_width,
+                    cells: cell_snapshots(border_map, |(key, dir)| (key.y, key.x, *dir as u8)),
+                }),
+            extents,
+            wrap: self.wrap.map(|wrap| wrap.bounds()),
+        }
+    }
+
+    /// 用快照替换当前内容，hasher保持不变
+    /// 快照不自洽时返回错误，map保持原样
+    pub fn restore(&mut self, snapshot: &SpaceMapSnapshot<V>) -> Result<(), SnapshotError> {
+        *self = self.restored(snapshot)?;
+        Ok(())
+    }
+
+    /// 用快照创建一个新map，hasher和cell复用的设置沿用当前的，当前map不变
+    pub fn restored(&self, snapshot: &SpaceMapSnapshot<V>) -> Result<Self, SnapshotError> {
+        let mut map = Self::from_snapshot_with_hasher(snapshot, self.map.hasher().clone())?;
+        map.evict_after = self.evict_after;
+        Ok(map)
+    }
+
+    /// 主层和边界层原样搬回来，cell内的顺序不变，实体的位置索引跟着重建
+    fn from_snapshot_with_hasher(
+        snapshot: &SpaceMapSnapshot<V>,
+        hasher: S,
+    ) -> Result<Self, SnapshotError> {
+        let mut map = Self::with_hasher(snapshot.cell_size, hasher);
+        map.wrap = snapshot
+            .wrap
+            .map(|(min, max)| Wrap::new(min, max, snapshot.cell_size));
+        if let Some(border) = snapshot.border.as_ref() {
+            if snapshot.cell_size.planar().is_none() {
+                return Err(SnapshotError::Invalid(
+                    "border layer is only supported in 2D".to_owned(),
+                ));
+            }
+            map.enable_border_layer(border.line_width);
+            let border_map = map.border_layer_map.as_mut().unwrap();
+            for cell in border.cells.iter() {
+                if border_map.insert(cell.key, index_grid(cell)?).is_some() {
+                    return Err(SnapshotError::Invalid(format!(
+                        "border cell {} {} listed twice",
+                        cell.key.0, cell.key.1
+                    )));
+                }
+            }
+        }
+        for cell in snapshot.cells.iter() {
+            if map.map.insert(cell.key, index_grid(cell)?).is_some() {
+                return Err(SnapshotError::Invalid(format!(
+                    "cell {:?} listed twice",
+                    cell.key
+                )));
+            }
+            for (index, id) in cell.ids.iter().enumerate() {
+                let slot = EntitySlot {
+                    cell: cell.key,
+                    index,
+                };
+                let id = *id as usize;
+                if id >= map.entity_slots.len() {
+                    map.entity_slots.resize(id + 1, None);
+                }
+                if map.entity_slots[id].replace(slot).is_some() {
+                    return Err(SnapshotError::Invalid(format!(
+                        "entity {} is in more than one cell",
+                        id
+                    )));
+                }
+            }
+            map.entity_count += cell.ids.len();
+        }
+        for (id, extent) in snapshot.extents.iter() {
+            let slot = map.entity_slots.get(*id as usize).copied().flatten();
+            let extent_before = map.extents.get(*id as usize).copied().flatten();
+            if slot.is_some() || extent_before.is_some() {
+                return Err(SnapshotError::Invalid(format!(
+                    "entity {} is stored twice",
+                    id
+                )));
+            }
+            map.insert_extent(*id, *extent);
+        }
+        Ok(map)
+    }
+}
+
+impl Space {
+    /// 所有支持快照的层（目前只有 `SpaceMap`），其它层会被跳过
+    pub fn snapshot(&self) -> SpaceSnapshot {
+        SpaceSnapshot {
+            levels: self
+                .levels()
+                .filter_map(|(name, level)| Some((name.to_owned(), level.snapshot()?)))
+                .collect(),
+        }
+    }
+
+    /// 按名字把快照恢复到已经注册的层，快照里没有的层不动
+    /// 先用快照建好所有的层再一起换上，任何一层失败时整个 `Space` 保持原样
+    pub fn restore(&mut self, snapshot: &SpaceSnapshot) -> Result<(), SnapshotError> {
+        let mut restored = Vec::with_capacity(snapshot.levels.len());
+        for (name, level_snapshot) in snapshot.levels.iter() {
+            let index = self
+                .levels
+                .iter()
+                .position(|level| level.name == name)
+                .ok_or_else(|| SnapshotError::UnknownLevel(name.clone()))?;
+            restored.push((
+                index,
+                self.levels[index].map.restored_level(level_snapshot)?,
+            ));
+        }
+        for (index, map) in restored {
+            self.levels[index].map = map;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn space_map_snapshot_round_trips() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(20);
+    for wrap in [false, true] {
+        let mut map = super::Collision::new(Vec2::new(20., 20.));
+        map.with_border_layer(2., 4.);
+        if wrap {
+            map.with_wrap(Vec2::new(-100., -100.), Vec2::new(130., 100.));
+        }
+        let positions: Vec<Vec2> = (0..200)
+            .map(|_| Vec2::new(rng.gen_range(-100.0..130.0), rng.gen_range(-100.0..100.0)))
+            .collect();
+        for (i, p) in positions.iter().enumerate() {
+            map.insert(i as u32, *p);
+        }
+        // 留下一些空cell，再加几个有大小的实体
+        for i in 0..20 {
+            map.remove(i);
+        }
+        map.insert_circle(300, Vec2::new(5., 5.), 30.);
+        map.insert_aabb(301, Vec2::new(-60., 20.), Vec2::new(-10., 45.));
+
+        let snapshot = map.snapshot();
+        let from_json = SpaceMapSnapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        let from_bytes = SpaceMapSnapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+        assert_eq!(from_json, snapshot);
+        assert_eq!(from_bytes, snapshot);
+        assert!(snapshot.to_bytes().unwrap().len() < snapshot.to_json().unwrap().len() / 2);
+
+        let loaded = super::Collision::from_snapshot(&from_bytes).unwrap();
+        assert_eq!(loaded.snapshot(), snapshot);
+        assert_eq!(loaded.stats().empty_cells, map.stats().empty_cells);
+        assert_eq!(loaded.is_wrapped(), wrap);
+        for p in positions.iter().take(30) {
+            assert_eq!(loaded.query_radius(*p, 6.), map.query_radius(*p, 6.));
+            assert_eq!(loaded.query_radius(*p, 45.), map.query_radius(*p, 45.));
+        }
+        // 加载后的map可以继续增量更新
+        let mut loaded = loaded;
+        loaded.update_position(50, positions[50], Vec2::new(1., 1.));
+        map.update_position(50, positions[50], Vec2::new(1., 1.));
+        assert_eq!(loaded.snapshot(), map.snapshot());
+    }
+}
+
+#[test]
+fn space_snapshot_saves_and_rejects_bad_data() {
+    use super::{Clustering, Collision, CollisionMarker};
+    let mut space = Space::default()
+        .with_level("collision", Collision::new(Vec2::new(10., 10.)))
+        .with_level("clustering", Clustering::new(Vec2::new(40., 40.)));
+    for i in 0..50 {
+        space.insert(i, Vec2::new(i as f32 * 3.7, (i % 7) as f32 * 9.1));
+    }
+    let snapshot = space.snapshot();
+    let dir = std::env::temp_dir().join(format!("space-snapshot-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["frame.json", "frame.bin"] {
+        let path = dir.join(file);
+        snapshot.save(&path).unwrap();
+        let loaded = SpaceSnapshot::load(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let mut restored = Space::default()
+            .with_level("collision", Collision::new(Vec2::new(10., 10.)))
+            .with_level("clustering", Clustering::new(Vec2::new(1., 1.)));
+        restored.restore(&loaded).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(
+            restored.index::<Clustering>().cell_size,
+            Vec2::new(40., 40.)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    let bytes = snapshot.to_bytes().unwrap();
+    assert!(matches!(
+        SpaceSnapshot::from_bytes(&bytes[1..]),
+        Err(SnapshotError::BadHeader)
+    ));
+    assert!(matches!(
+        SpaceSnapshot::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(SnapshotError::Binary(_))
+    ));
+    // 同一个实体出现在两个cell里，恢复失败且原来的内容不变
+    let mut broken = snapshot.clone();
+    let cells = &mut broken.levels[0].1.cells;
+    let (id, position) = (cells[0].ids[0], cells[0].positions[0]);
+    cells[1].ids.push(id);
+    cells[1].positions.push(position);
+    let before = space.snapshot();
+    assert!(matches!(
+        space.restore(&broken),
+        Err(SnapshotError::Invalid(_))
+    ));
+    assert_eq!(space.snapshot(), before);
+    assert!(space
+        .level::<CollisionMarker>()
+        .query_radius(Vec2::ZERO, 1.)
+        .contains(&0));
+
+    // 最后一层坏掉时前面的层也不能被换掉
+    let mut broken_last = snapshot.clone();
+    for i in 0..50 {
+        space.update_position(
+            i,
+            Vec2::new(i as f32 * 3.7, (i % 7) as f32 * 9.1),
+            Vec2::ZERO,
+        );
+    }
+    let cells = &mut broken_last.levels.last_mut().unwrap().1.cells;
+    cells[0].positions.pop();
+    let before = space.snapshot();
+    assert!(matches!(
+        space.restore(&broken_last),
+        Err(SnapshotError::Invalid(_))
+    ));
+    assert_eq!(space.snapshot(), before);
+    assert_eq!(
+        space
+            .level::<CollisionMarker>()
+            .query_radius(Vec2::ZERO, 1.)
+            .len(),
+        50
+    );
+
+    broken.levels[0].0 = "view".to_owned();
+    assert!(matches!(
+        space.restore(&broken),
+        Err(SnapshotError::UnknownLevel(name)) if name == "view"
+    ));
+}
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, fmt, hash::BuildHasher, mem::size_of};
+
+use glam::Vec2;
+
+use super::{
+    dim::SpaceCell,
+    unit::{EntitySlot, IndexGrid},
+    Payload, Space, SpaceMap, SpaceVector,
+};
+
+/// 主层的占用统计，用来判断cell大小是否合适
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpaceStats<V = Vec2> {
+    pub cell_size: V,
+    /// 点实体的数量（不含有大小的实体）
+    pub entities: usize,
+    /// 至少有一个实体的cell数
+    pub occupied_cells: usize,
+    /// map里已经空了但还没释放的cell数
+    pub empty_cells: usize,
+    /// 有实体的cell里实体数的最小/平均/最大值
+    pub min: usize,
+    pub mean: f32,
+    pub max: usize,
+    /// 有实体的cell里实体数的分位数
+    pub p50: usize,
+    pub p90: usize,
+    pub p99: usize,
+    /// `histogram[k]` 是实体数在 `[2^k, 2^(k+1))` 之间的cell数
+    pub histogram: Vec<usize>,
+    /// 主层、边界层、有大小实体和id索引一共分配的字节数（按容量算）
+    pub allocated_bytes: usize,
+    /// 以随机实体为中心、覆盖周围一圈cell（2D是3x3，3D是3x3x3）的范围查询平均要做的距离判断次数
+    pub estimated_query_cost: f32,
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    pub fn stats(&self) -> SpaceStats<V> {
+        let mut counts: Vec<usize> = self.map.values().map(|grid| grid.payloads.len()).collect();
+        let cells = counts.len();
+        counts.retain(|count| *count > 0);
+        counts.sort_unstable();
+        let entities: usize = counts.iter().sum();
+        let mut histogram = Vec::new();
+        for count in counts.iter() {
+            let bucket = count.ilog2() as usize;
+            if bucket >= histogram.len() {
+                histogram.resize(bucket + 1, 0);
+            }
+            histogram[bucket] += 1;
+        }
+        // 最近秩分位数
+        let percentile = |p: f32| {
+            if counts.is_empty() {
+                return 0;
+            }
+            let rank = (p * counts.len() as f32).ceil() as usize;
+            counts[rank.clamp(1, counts.len()) - 1]
+        };
+        // 查询点所在cell的实体数按实体加权，邻居cell近似取同样的密度
+        let squared: usize = counts.iter().map(|count| count * count).sum();
+        let estimated_query_cost = if entities == 0 {
+            0.
+        } else {
+            3u32.pow(V::Cell::DIM) as f32 * squared as f32 / entities as f32
+        };
+
+        SpaceStats {
+            cell_size: self.cell_size,
+            entities,
+            occupied_cells: counts.len(),
+            empty_cells: cells - counts.len(),
+            min: counts.first().copied().unwrap_or(0),
+            mean: if counts.is_empty() {
+                0.
+            } else {
+                entities as f32 / counts.len() as f32
+            },
+            max: counts.last().copied().unwrap_or(0),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            histogram,
+            allocated_bytes: self.allocated_bytes(),
+            estimated_query_cost,
+        }
+    }
+
+    fn allocated_bytes(&self) -> usize {
+        fn map_bytes<K, S, X, P>(map: &HashMap<K, IndexGrid<X, P>, S>) -> usize {
+            map.capacity() * size_of::<(K, IndexGrid<X, P>)>()
+                + map
+                    .values()
+                    .map(|grid| {
+                        grid.payloads.capacity() * size_of::<P>()
+                            + grid.positions.capacity() * size_of::<X>()
+                    })
+                    .sum::<usize>()
+        }
+        let extent_bytes = self.extent_map.capacity() * size_of::<(V::Cell, Vec<u32>)>()
+            + self
+                .extent_map
+                .values()
+                .map(|ids| ids.capacity() * size_of::<u32>())
+                .sum::<usize>()
+            + self.extents.capacity() * size_of::<Option<super::extent::Extent<V>>>();
+        map_bytes(&self.map)
+            + self.border_layer_map.as_ref().map_or(0, map_bytes)
+            + extent_bytes
+            + self.entity_slots.capacity() * size_of::<Option<EntitySlot<V::Cell>>>()
+    }
+}
+
+impl<V: SpaceVector> fmt::Display for SpaceStats<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cell_size: Vec<String> = self
+            .cell_size
+            .components()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        write!(
+            f,
+            "cell {}: {} entities in {} cells ({} empty), per cell min {} mean {:.1} max {} \
+             p50 {} p90 {} p99 {}, histogram {:?}, {} KiB, query cost ~{:.0}",
+            cell_size.join("x"),
+            self.entities,
+            self.occupied_cells,
+            self.empty_cells,
+            self.min,
+            self.mean,
+            self.max,
+            self.p50,
+            self.p90,
+            self.p99,
+            self.histogram,
+            self.allocated_bytes / 1024,
+            self.estimated_query_cost,
+        )
+    }
+}
+
+impl Space {
+    /// 每一层的占用统计，不支持统计的索引会被跳过
+    pub fn stats(&self) -> impl Iterator<Item = (&'static str, SpaceStats)> + '_ {
+        self.levels()
+            .filter_map(|(name, level)| Some((name, level.stats()?)))
+    }
+}
+
+#[test]
+fn stats_count_cells_and_percentiles() {
+    let mut map = super::Collision::new(Vec2::new(10., 10.));
+    // 三个cell分别有 1 / 2 / 5 个实体
+    let mut id = 0;
+    for (cell, count) in [
+        (Vec2::new(5., 5.), 1),
+        (Vec2::new(15., 5.), 2),
+        (Vec2::new(5., 25.), 5),
+    ] {
+        for _ in 0..count {
+            map.insert(id, cell);
+            id += 1;
+        }
+    }
+    // 移走唯一的实体后留下一个空cell
+    map.insert(id, Vec2::new(-35., 5.));
+    map.remove(id);
+
+    let stats = map.stats();
+    assert_eq!(stats.entities, 8);
+    assert_eq!(stats.occupied_cells, 3);
+    assert_eq!(stats.empty_cells, 1);
+    assert_eq!((stats.min, stats.max), (1, 5));
+    assert!((stats.mean - 8. / 3.).abs() < 1e-5);
+    assert_eq!((stats.p50, stats.p90, stats.p99), (2, 5, 5));
+    assert_eq!(stats.histogram, vec![1, 1, 1]);
+    assert!((stats.estimated_query_cost - 9. * 30. / 8.).abs() < 1e-4);
+    assert!(stats.allocated_bytes > 0);
+}
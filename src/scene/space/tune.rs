@@ -0,0 +1,134 @@
+use std::{fmt, hash::BuildHasher};
+
+use glam::Vec2;
+
+use super::{Payload, SpaceMap, SpaceStats, SpaceVector};
+
+/// 按查询半径给出cell大小，半径 `r` 的范围查询最多看3x3个cell
+/// 开启边界层时cell至少要是边界带的两倍宽，否则边界层的捷径用不上
+pub fn cell_size_for(query_radius: f32, border_line_width: Option<f32>) -> f32 {
+    border_line_width.map_or(query_radius, |width| query_radius.max(2. * width))
+}
+
+/// 运行时根据占用统计调整cell大小的规则
+#[derive(Clone, Copy, Debug)]
+pub struct CellTuning {
+    pub min_cell: f32,
+    pub max_cell: f32,
+    /// 有实体的cell平均实体数低于它说明cell太细，大部分查询在看空cell
+    pub min_mean: f32,
+    /// 高于它说明cell太粗，每次查询要过滤太多实体
+    pub max_mean: f32,
+}
+
+impl CellTuning {
+    /// 在 `cell_size_for` 给出的大小上下各留出一些调整空间
+    pub fn for_radius(query_radius: f32, border_line_width: Option<f32>) -> Self {
+        let base = cell_size_for(query_radius, border_line_width);
+        let min_cell = border_line_width.map_or(base / 2., |width| (base / 2.).max(2. * width));
+        CellTuning {
+            min_cell,
+            max_cell: base * 4.,
+            min_mean: 1.5,
+            max_mean: 12.,
+        }
+    }
+
+    /// 需要换cell大小时返回新的大小，每次放大或缩小一倍
+    /// 2D里边长翻倍平均占用大约变成4倍，上下限之间留的余量足够不来回抖动
+    pub fn suggest<V: SpaceVector>(&self, stats: &SpaceStats<V>) -> Option<V> {
+        if stats.entities == 0 {
+            return None;
+        }
+        let factor = if stats.mean > self.max_mean {
+            0.5
+        } else if stats.mean < self.min_mean {
+            2.
+        } else {
+            return None;
+        };
+        let cell_size =
+            (stats.cell_size * factor).clamp(V::splat(self.min_cell), V::splat(self.max_cell));
+        // 已经贴着上下限了（环面世界会把cell微调到整数个），不再重建
+        let change = (cell_size - stats.cell_size).abs().max_element();
+        (change > stats.cell_size.min_element() * 0.1).then_some(cell_size)
+    }
+}
+
+/// `Space::auto_tune` 换过cell大小的一层
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellRetune {
+    pub level: &'static str,
+    pub old_cell_size: Vec2,
+    /// 实际用上的大小，环面世界里会微调到整数个cell
+    pub new_cell_size: Vec2,
+    /// 调整前有实体的cell平均实体数
+    pub mean: f32,
+}
+
+impl fmt::Display for CellRetune {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "retune {}: cell {} -> {} (mean {:.1} per cell)",
+            self.level, self.old_cell_size, self.new_cell_size, self.mean
+        )
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 换一个cell大小，已有的实体全部按新的网格重新插入
+    /// 环面世界仍然会把cell微调到刚好铺满世界
+    pub fn set_cell_size(&mut self, cell_size: V) {
+        match self.wrap {
+            Some(wrap) => {
+                let (min, max) = wrap.bounds();
+                self.cell_size = cell_size;
+                self.with_wrap(min, max);
+            }
+            None => self.rebuild_with(|map| map.cell_size = cell_size),
+        }
+    }
+}
+
+#[test]
+fn tuning_rehashes_towards_target_occupancy() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(19);
+    let mut map = super::Collision::new(Vec2::new(10., 10.));
+    map.with_border_layer(2., 4.);
+    map.with_wrap(Vec2::ZERO, Vec2::new(400., 400.));
+    // 600个实体铺在400x400里，10x10的cell太细
+    let positions: Vec<Vec2> = (0..600)
+        .map(|_| Vec2::new(rng.gen_range(0.0..400.0), rng.gen_range(0.0..400.0)))
+        .collect();
+    for (i, p) in positions.iter().enumerate() {
+        map.insert(i as u32, *p);
+    }
+    let tuning = CellTuning::for_radius(30., Some(8.));
+    let before: Vec<Vec<u32>> = positions[..20]
+        .iter()
+        .map(|p| {
+            let mut ids = map.query_radius(*p, 30.);
+            ids.sort();
+            ids
+        })
+        .collect();
+    let mut rehashes = 0;
+    while let Some(cell_size) = tuning.suggest(&map.stats()) {
+        map.set_cell_size(cell_size);
+        rehashes += 1;
+        assert!(rehashes < 10);
+    }
+    let stats = map.stats();
+    assert!(rehashes > 0);
+    assert!(stats.mean >= tuning.min_mean && stats.mean <= tuning.max_mean);
+    assert_eq!(stats.entities, positions.len());
+    assert!(map.is_wrapped() && map.has_border_layer());
+    // 重建前后查询结果一致
+    for (p, expected) in positions[..20].iter().zip(before) {
+        let mut ids = map.query_radius(*p, 30.);
+        ids.sort();
+        assert_eq!(ids, expected);
+    }
+}
@@ -1,43 +1,177 @@
-pub struct IndexGrid {
-    pub entity_ids: Vec<u32>,
+use std::hash::{BuildHasherDefault, Hasher};
+
+use glam::{IVec2, Vec2};
+
+use super::{BorderDir, SpaceVector};
+
+/// cell里每个实体存的内容，默认只存实体id
+/// 邻居循环要读的数据（比如速度）可以一起存进来，查询时不用再回到实体数组里取
+pub trait Payload: Copy {
+    fn id(&self) -> u32;
+}
+
+impl Payload for u32 {
+    fn id(&self) -> u32 {
+        *self
+    }
+}
+
+/// `(id, 附带数据)`
+impl<X: Copy> Payload for (u32, X) {
+    fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+/// 以实体下标为id的payload，`SpatialIndex`、并行重建和快照恢复都只知道id
+/// 附带的速度由 `update_velocities` 每帧写入
+pub trait IndexPayload<V>: Payload {
+    /// 是否存了速度，没存时 `update_velocities` 直接跳过
+    const HAS_VELOCITY: bool = false;
+    fn from_id(id: u32) -> Self;
+    /// 实体数组重排之后原地改写id
+    fn set_id(&mut self, id: u32);
+    fn velocity(&self) -> Option<V> {
+        None
+    }
+    fn set_velocity(&mut self, _velocity: V) {}
+}
+
+impl<V> IndexPayload<V> for u32 {
+    fn from_id(id: u32) -> Self {
+        id
+    }
+    fn set_id(&mut self, id: u32) {
+        *self = id;
+    }
+}
+
+/// `(id, 速度)`，聚类层的邻居循环直接读存着的速度
+impl<V: SpaceVector> IndexPayload<V> for (u32, V) {
+    const HAS_VELOCITY: bool = true;
+    fn from_id(id: u32) -> Self {
+        (id, V::ZERO)
+    }
+    fn set_id(&mut self, id: u32) {
+        self.0 = id;
+    }
+    fn velocity(&self) -> Option<V> {
+        Some(self.1)
+    }
+    fn set_velocity(&mut self, velocity: V) {
+        self.1 = velocity;
+    }
+}
+
+pub struct IndexGrid<V = Vec2, P = u32> {
+    pub payloads: Vec<P>,
+    // 和 payloads 一一对应，插入时的位置，用于范围查询的距离过滤
+    pub positions: Vec<V>,
+    // 保留分配的重建模式下，这个cell连续空了多少次重建
+    pub(super) idle_frames: u32,
 }
 
-impl IndexGrid {
+impl<V: Copy, P: Payload> IndexGrid<V, P> {
     pub fn new() -> Self {
         IndexGrid {
-            entity_ids: Vec::new(),
+            payloads: Vec::new(),
+            positions: Vec::new(),
+            idle_frames: 0,
         }
     }
-    pub fn insert(&mut self, ids: u32) {
-        self.entity_ids.push(ids);
+    pub fn insert(&mut self, payload: P, position: V) {
+        self.payloads.push(payload);
+        self.positions.push(position);
+    }
+    /// 默认的payload就是实体id
+    pub fn get_entities(&self) -> &[P] {
+        &self.payloads
+    }
+    pub fn get_positions(&self) -> &[V] {
+        &self.positions
+    }
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.payloads.iter().map(Payload::id)
     }
-    pub fn get_entities(&self) -> &[u32] {
-        &self.entity_ids
+    /// 删除下标为 `index` 的实体，最后一个实体会被挪到这个位置，返回被挪动的实体id
+    pub fn swap_remove(&mut self, index: usize) -> Option<u32> {
+        self.payloads.swap_remove(index);
+        self.positions.swap_remove(index);
+        self.payloads.get(index).map(Payload::id)
+    }
+    /// 清空但保留容量，返回这个cell已经连续空了几次
+    pub(super) fn truncate(&mut self) -> u32 {
+        if self.payloads.is_empty() {
+            self.idle_frames += 1;
+        } else {
+            self.idle_frames = 0;
+        }
+        self.payloads.clear();
+        self.positions.clear();
+        self.idle_frames
     }
 }
 
-pub struct PosString {
-    pub value: String,
+/// 实体当前所在的cell以及它在该cell的 `IndexGrid` 中的下标
+#[derive(Clone, Copy, Debug)]
+pub struct EntitySlot<C = CellKey> {
+    pub cell: C,
+    pub index: usize,
 }
-use glam::IVec2;
 
-use super::BorderDir;
-impl From<IVec2> for PosString {
-    fn from(value: IVec2) -> Self {
-        PosString {
-            value: String::from_iter([value.x.to_string(), ",".to_string(), value.y.to_string()]),
-        }
+/// 主层的key，直接用cell的整数坐标
+pub type CellKey = IVec2;
+/// 边界层的key，cell坐标加上所在的角
+pub type BorderKey = (IVec2, BorderDir);
+
+/// 给cell key用的快速hasher（FxHash的乘法-旋转方案）
+/// key只是几个i32，不需要SipHash那种抗碰撞攻击的强度
+#[derive(Default, Clone, Copy)]
+pub struct CellHasher {
+    hash: u64,
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+impl CellHasher {
+    #[inline]
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
     }
 }
-impl From<(IVec2, BorderDir)> for PosString {
-    fn from(value: (IVec2, BorderDir)) -> Self {
-        PosString {
-            value: String::from_iter([
-                value.0.x.to_string(),
-                ",".to_string(),
-                value.0.y.to_string(),
-                value.1.to_string(),
-            ]),
+
+impl Hasher for CellHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.add_to_hash(*byte as u64);
         }
     }
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add_to_hash(i as u64);
+    }
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add_to_hash(i as u64);
+    }
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.add_to_hash(i as u32 as u64);
+    }
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add_to_hash(i);
+    }
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add_to_hash(i as u64);
+    }
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.hash
+    }
 }
+
+/// SpaceMap默认使用的hasher，可以换成任何 `BuildHasher`
+pub type CellBuildHasher = BuildHasherDefault<CellHasher>;
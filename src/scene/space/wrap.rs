@@ -0,0 +1,215 @@
+use std::{collections::HashSet, hash::BuildHasher};
+
+use glam::Vec2;
+
+use super::{dim::SpaceCell, extent::Extent, Payload, SpaceMap, SpaceVector};
+
+/// 首尾相接的世界范围，每个方向上都是整数个cell
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Wrap<V: SpaceVector = Vec2> {
+    min: V,
+    size: V,
+    cells: V::Cell,
+}
+
+impl<V: SpaceVector> Wrap<V> {
+    /// 把 `[min, max)` 分成最接近 `cell_size` 的整数个cell
+    pub(super) fn new(min: V, max: V, cell_size: V) -> Self {
+        let size = max - min;
+        let cells = (size / cell_size).round().as_cell().max(V::Cell::ONE);
+        Wrap { min, size, cells }
+    }
+
+    /// 铺满世界的cell大小
+    pub(super) fn cell_size(&self) -> V {
+        self.size / V::from_cell(self.cells)
+    }
+
+    pub(super) fn wrap_cell(&self, cell_pos: V::Cell) -> V::Cell {
+        cell_pos.rem_euclid(self.cells)
+    }
+
+    pub(super) fn wrap_position(&self, position: V) -> V {
+        self.min + (position - self.min).rem_euclid(self.size)
+    }
+
+    pub(super) fn bounds(&self) -> (V, V) {
+        (self.min, self.min + self.size)
+    }
+}
+
+impl<T, S: BuildHasher + Clone, P: Payload, V: SpaceVector> SpaceMap<T, S, P, V> {
+    /// 把世界 `[min, max)` 当成环面，超出一边的位置从另一边绕回来
+    /// cell大小会微调到刚好铺满世界，cell坐标对网格尺寸取模
+    /// 范围查询按最近镜像（minimum image）计算距离，接缝两边的实体互相可见
+    pub fn with_wrap(&mut self, min: V, max: V) {
+        let wrap = Wrap::new(min, max, self.cell_size);
+        self.rebuild_with(|map| {
+            map.cell_size = wrap.cell_size();
+            map.wrap = Some(wrap);
+        });
+    }
+
+    pub fn is_wrapped(&self) -> bool {
+        self.wrap.is_some()
+    }
+
+    /// 从 `from` 指向 `to` 的向量，环面世界里取最短的那个镜像
+    pub fn displacement(&self, from: V, to: V) -> V {
+        let d = to - from;
+        match self.wrap {
+            Some(wrap) => d - (d / wrap.size).round() * wrap.size,
+            None => d,
+        }
+    }
+
+    /// 和 `query_radius` 一样，同时返回从 `pos` 指向每个实体的最近镜像向量
+    /// 有大小的实体给出指向它中心的向量
+    pub fn query_radius_offsets(&self, pos: V, r: f32) -> Vec<(u32, V)> {
+        let mut result = Vec::new();
+        self.for_each_in_radius(pos, r, |id, offset| result.push((id, offset)));
+        result
+    }
+
+    /// 位置绕回到世界范围内，没开启环面时原样返回
+    pub(super) fn wrap_position(&self, position: V) -> V {
+        self.wrap
+            .map_or(position, |wrap| wrap.wrap_position(position))
+    }
+
+    /// 未取模的cell坐标对网格尺寸取模，得到map里的key
+    pub(super) fn wrap_cell(&self, cell_pos: V::Cell) -> V::Cell {
+        self.wrap.map_or(cell_pos, |wrap| wrap.wrap_cell(cell_pos))
+    }
+
+    /// cell坐标的原点，环面世界从 `min` 开始划分
+    pub(super) fn cell_origin(&self) -> V {
+        self.wrap.map_or(V::ZERO, |wrap| wrap.min)
+    }
+
+    /// 访问未取模的cell盒子 `[min_cell, max_cell]`，给出未取模坐标和map里的key
+    /// 环面世界里超过一圈的部分会被截掉，每个key只访问一次
+    pub(super) fn for_each_cell(
+        &self,
+        min_cell: V::Cell,
+        mut max_cell: V::Cell,
+        mut f: impl FnMut(V::Cell, V::Cell),
+    ) {
+        if let Some(wrap) = self.wrap {
+            max_cell = max_cell.min(min_cell + wrap.cells - V::Cell::ONE);
+        }
+        V::for_each_cell(min_cell, max_cell, |cell_pos| {
+            f(cell_pos, self.wrap_cell(cell_pos))
+        });
+    }
+
+    /// 网格在每个方向上都至少有3个cell，否则邻居cell会取到重复的key
+    pub(super) fn wrap_has_distinct_neighbors(&self) -> bool {
+        match self.wrap {
+            Some(wrap) => wrap.cells.min_element() >= 3,
+            None => true,
+        }
+    }
+
+    /// `pos` 到有大小实体最近镜像的距离平方
+    pub(super) fn extent_distance_squared(&self, extent: &Extent<V>, pos: V) -> f32 {
+        let center = extent.center();
+        extent.distance_squared(center + self.displacement(center, pos))
+    }
+
+    /// 点是否在轴对齐矩形 `[min, max]` 的某个镜像内
+    pub(super) fn aabb_contains(&self, min: V, max: V, position: V) -> bool {
+        match self.wrap {
+            Some(wrap) => {
+                let position = min + (position - min).rem_euclid(wrap.size);
+                position.le_all(max)
+            }
+            None => position.within(min, max),
+        }
+    }
+
+    /// 有大小实体的最近镜像（相对矩形中心）是否和矩形相交
+    pub(super) fn extent_intersects_aabb(&self, extent: &Extent<V>, min: V, max: V) -> bool {
+        let center = extent.center();
+        let query_center = (min + max) / 2.;
+        let image = query_center - self.displacement(center, query_center);
+        extent.translated(image - center).intersects_aabb(min, max)
+    }
+
+    /// 环面世界里有大小的实体可能在同一次查询中从不同方向被看到，用集合去重
+    pub(super) fn extent_dedup(&self) -> Option<HashSet<u32>> {
+        self.wrap.map(|_| HashSet::new())
+    }
+}
+
+#[test]
+fn wrapped_query_sees_across_the_seam() {
+    let mut map = super::Collision::new(Vec2::new(30., 30.));
+    map.with_wrap(Vec2::new(-10., -10.), Vec2::new(290., 190.));
+    map.insert(0, Vec2::new(-8., 50.));
+    map.insert(1, Vec2::new(287., 52.));
+    map.insert(2, Vec2::new(140., 50.));
+    // 超出世界的位置会绕回来
+    map.insert(3, Vec2::new(-12., 185.));
+
+    let mut ids = map.query_radius(Vec2::new(-9., 50.), 10.);
+    ids.sort();
+    assert_eq!(ids, vec![0, 1]);
+    let mut offsets = map.query_radius_offsets(Vec2::new(-9., 50.), 10.);
+    offsets.sort_by_key(|(id, _)| *id);
+    assert!(offsets[0].1.abs_diff_eq(Vec2::new(1., 0.), 1e-3));
+    assert!(offsets[1].1.abs_diff_eq(Vec2::new(-4., 2.), 1e-3));
+    // 四个角在环面上是相邻的
+    assert_eq!(map.query_radius(Vec2::new(289., -9.), 7.), vec![3]);
+    assert!(map
+        .displacement(Vec2::new(288., 185.), Vec2::new(-8., -8.))
+        .abs_diff_eq(Vec2::new(4., 7.), 1e-3));
+    assert_eq!(map.nearest(Vec2::new(291., 50.)), Some(0));
+    let mut ids = map.query_aabb(Vec2::new(280., 40.), Vec2::new(295., 60.));
+    ids.sort();
+    assert_eq!(ids, vec![0, 1]);
+}
+
+#[test]
+fn wrapped_queries_match_brute_force() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+    let min = Vec2::new(-20., 5.);
+    let max = Vec2::new(380., 245.);
+    let size = max - min;
+    let min_image = |d: Vec2| d - (d / size).round() * size;
+    let mut map = super::Collision::new(Vec2::new(35., 35.));
+    map.with_border_layer(3., 10.);
+    let mut positions: Vec<Vec2> = (0..400)
+        .map(|_| Vec2::new(rng.gen_range(-60.0..420.0), rng.gen_range(-30.0..280.0)))
+        .collect();
+    for (i, p) in positions.iter().enumerate() {
+        map.insert(i as u32, *p);
+    }
+    // 插入之后再开启也会把已有实体放到正确的cell
+    map.with_wrap(min, max);
+    for _ in 0..5 {
+        for (i, p) in positions.iter_mut().enumerate() {
+            let old = *p;
+            *p += Vec2::new(rng.gen_range(-30.0..30.0), rng.gen_range(-30.0..30.0));
+            map.update_position(i as u32, old, *p);
+        }
+        for _ in 0..30 {
+            let pos = Vec2::new(rng.gen_range(-40.0..400.0), rng.gen_range(-20.0..260.0));
+            for r in [8., 50., 160.] {
+                let expected: Vec<u32> = (0..positions.len())
+                    .filter(|i| min_image(positions[*i] - pos).length_squared() <= r * r)
+                    .map(|i| i as u32)
+                    .collect();
+                let mut got = map.query_radius(pos, r);
+                got.sort();
+                assert_eq!(got, expected);
+            }
+            let nearest = (0..positions.len())
+                .map(|i| (min_image(positions[i] - pos).length_squared(), i as u32))
+                .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+                .map(|(_, i)| i);
+            assert_eq!(map.nearest(pos), nearest);
+        }
+    }
+}